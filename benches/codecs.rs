@@ -0,0 +1,143 @@
+//! Throughput and compression of the encoders and decoders on synthetic
+//! datasets, so regressions in either are visible.
+//!
+//! Run with `cargo bench --bench codecs`.
+
+extern crate gibbon;
+
+use gibbon::*;
+use gibbon::vec_stream::{VecWriter, VecReader};
+
+mod common;
+use common::Random;
+
+const SAMPLES: usize = 100_000;
+const HEADER_TIME: u64 = 1496361600;
+
+struct Dataset {
+    name: &'static str,
+    samples: Vec<(u64, f64)>,
+}
+
+/// Samples every ten seconds, with values from `value`.
+fn regular<F>(name: &'static str, mut value: F) -> Dataset where F: FnMut(usize) -> f64 {
+    Dataset {
+        name,
+        samples: (0..SAMPLES).map(|i| (HEADER_TIME + 10 * i as u64, value(i))).collect(),
+    }
+}
+
+fn datasets() -> Vec<Dataset> {
+    let mut random = Random::new(1);
+    let mut walk = 0f64;
+    let random_walk = regular("random walk", |_| {
+        walk += random.next_f64() - 0.5;
+        walk
+    });
+
+    let mut random = Random::new(2);
+    let noisy_gauge = regular("noisy gauge", |i| {
+        let daily = (i as f64 * 10.0 / 86400.0 * std::f64::consts::TAU).sin();
+        50.0 + 10.0 * daily + random.next_f64()
+    });
+
+    // up to two seconds early or late, and the odd missed sample
+    let mut random = Random::new(3);
+    let mut timestamp = HEADER_TIME;
+    let jittery = Dataset {
+        name: "jittery timestamps",
+        samples: (0..SAMPLES).map(|i| {
+            timestamp += if random.next_u64() % 100 == 0 { 20 } else { 10 };
+            let jitter = random.next_u64() % 5;
+            (timestamp + jitter - 2, (i % 100) as f64)
+        }).collect(),
+    };
+
+    vec![
+        regular("constant", |_| 42.0),
+        regular("counter", |i| (i * 3) as f64),
+        random_walk,
+        noisy_gauge,
+        jittery,
+    ]
+}
+
+fn report(name: &str, ns: f64, bits: usize) {
+    println!("  {:30} {:8.2} ns/sample {:10.2} Msamples/s {:8.2} bits/sample",
+             name, ns, 1000.0 / ns, bits as f64 / SAMPLES as f64);
+}
+
+/// Times `encode` and reports the size of what it wrote, which is returned
+/// for the decoder benchmarks.
+fn bench_encoder<F>(name: &str, mut encode: F) -> VecWriter where F: FnMut(&mut VecWriter) {
+    let ns = common::measure(SAMPLES, || {
+        let mut w = VecWriter::new();
+        encode(&mut w);
+        w.len()
+    });
+
+    let mut w = VecWriter::new();
+    encode(&mut w);
+    report(name, ns, w.len());
+    w
+}
+
+/// Times `decode` reading all of `w`.
+fn bench_decoder<T, F>(name: &str, w: &VecWriter, mut decode: F) where F: FnMut(VecReader) -> T {
+    let ns = common::measure(SAMPLES, || decode(VecReader::new(&w.bit_vector, w.used_bits_last_elm)));
+    report(name, ns, w.len());
+}
+
+fn run(dataset: &Dataset) {
+    println!("{}:", dataset.name);
+    let samples = &dataset.samples;
+
+    let timestamps = bench_encoder("TimestampStreamWriter", |w| {
+        let mut c = TimestampStreamWriter::new(HEADER_TIME);
+        for &(timestamp, _) in samples {
+            c.push(timestamp, w);
+        }
+    });
+
+    let doubles = bench_encoder("DoubleStreamWriter", |w| {
+        let mut c = DoubleStreamWriter::new();
+        for &(_, value) in samples {
+            c.push(value, w);
+        }
+    });
+
+    let lead_trail = bench_encoder("DoubleStreamLeadTrail", |w| {
+        let mut c = DoubleStreamLeadTrail::new();
+        for &(_, value) in samples {
+            c.push(value, w);
+        }
+    });
+
+    let mut w = VecWriter::new();
+    let mut c = TimeAndValueStream::new(HEADER_TIME);
+    for &(timestamp, value) in samples {
+        c.push(timestamp, value, &mut w);
+    }
+
+    bench_decoder("TimestampStreamIterator", &timestamps, |r| {
+        TimestampStreamIterator::new(r, HEADER_TIME).fold(0, |acc, timestamp| acc ^ timestamp)
+    });
+
+    bench_decoder("DoubleStreamIterator", &doubles, |r| {
+        DoubleStreamIterator::new(r).sum::<f64>()
+    });
+
+    bench_decoder("DoubleStreamLeadTrailIterator", &lead_trail, |r| {
+        DoubleStreamLeadTrailIterator::new(r).sum::<f64>()
+    });
+
+    bench_decoder("TimeAndValueIterator", &w, |r| {
+        TimeAndValueIterator::new(r, HEADER_TIME).fold(0f64, |acc, (timestamp, value)| acc + timestamp as f64 + value)
+    });
+}
+
+fn main() {
+    for dataset in datasets().iter() {
+        run(dataset);
+    }
+}
@@ -0,0 +1,47 @@
+//! Helpers shared by the benchmarks. They use `harness = false` and only std,
+//! so run on stable without extra dependencies.
+
+#![allow(dead_code)]
+
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+/// Runs `f` until it has taken at least half a second, and returns the time
+/// per sample in nanoseconds. `f` returns something derived from the samples,
+/// so the work isn't optimized away.
+pub fn measure<T, F>(samples: usize, mut f: F) -> f64 where F: FnMut() -> T {
+    let mut iterations = 1;
+    loop {
+        let start = Instant::now();
+        for _ in 0..iterations {
+            black_box(f());
+        }
+        let elapsed = start.elapsed();
+
+        if elapsed >= Duration::from_millis(500) {
+            return elapsed.as_nanos() as f64 / (iterations * samples) as f64;
+        }
+        iterations *= 2;
+    }
+}
+
+/// A PCG style generator, so datasets are the same on every run.
+pub struct Random {
+    state: u64,
+}
+
+impl Random {
+    pub fn new(seed: u64) -> Self {
+        Random { state: seed }
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+        self.state >> 33
+    }
+
+    /// Uniform in `0.0..1.0`.
+    pub fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 7) as f64 / (1u64 << 24) as f64
+    }
+}
@@ -0,0 +1,96 @@
+//! Compares `VecReader` with `BufferedVecReader`, on the samples in
+//! `examples/test_data.csv` and on a million synthetic samples.
+//!
+//! Run with `cargo bench --bench readers`.
+
+extern crate gibbon;
+
+use gibbon::*;
+use gibbon::vec_stream::{VecWriter, VecReader, BufferedVecReader};
+use std::fs;
+
+mod common;
+use common::Random;
+
+fn bench<T, F>(name: &str, samples: usize, f: F) where F: FnMut() -> T {
+    let ns = common::measure(samples, f);
+    println!("  {:32} {:8.2} ns/sample {:10.2} Msamples/s", name, ns, 1000.0 / ns);
+}
+
+fn csv_samples() -> Vec<(u64, f64)> {
+    let path = concat!(env!("CARGO_MANIFEST_DIR"), "/examples/test_data.csv");
+    fs::read_to_string(path).unwrap().lines().map(|line| {
+        let mut fields = line.split(',');
+        let timestamp = fields.next().unwrap().trim().parse().unwrap();
+        let value = fields.next().unwrap().trim().parse().unwrap();
+        (timestamp, value)
+    }).collect()
+}
+
+/// A gauge sampled every minute with some jitter.
+fn synthetic_samples(count: usize) -> Vec<(u64, f64)> {
+    let mut random = Random::new(0x2545F4914F6CDD1D);
+    let mut timestamp = 1496366523;
+    let mut value = 50.0;
+    (0..count).map(|_| {
+        timestamp += 59 + random.next_u64() % 3;
+        value += (random.next_u64() % 5) as f64 - 2.0;
+        (timestamp, value)
+    }).collect()
+}
+
+fn run(name: &str, samples: &[(u64, f64)]) {
+    let header_time = samples[0].0 - samples[0].0 % 3600;
+    let mut w = VecWriter::new();
+    let mut c = TimeAndValueStream::new(header_time);
+    for &(timestamp, value) in samples {
+        c.push(timestamp, value, &mut w);
+    }
+    let count = samples.len();
+
+    println!("{} ({} samples, {} bytes):", name, count, w.bit_vector.len() * 8);
+
+    bench("VecReader", count, || {
+        let r = TimeAndValueIterator::new(VecReader::new(&w.bit_vector, w.used_bits_last_elm), header_time);
+        r.map(|(_timestamp, value)| value).sum::<f64>()
+    });
+
+    bench("BufferedVecReader", count, || {
+        let r = TimeAndValueIterator::new(BufferedVecReader::new(&w.bit_vector, w.used_bits_last_elm), header_time);
+        r.map(|(_timestamp, value)| value).sum::<f64>()
+    });
+
+    let mut timestamps = vec![0u64; 1024];
+    let mut values = vec![0f64; 1024];
+    bench("decode_into (VecReader)", count, || {
+        let mut r = TimeAndValueIterator::new(VecReader::new(&w.bit_vector, w.used_bits_last_elm), header_time);
+        let mut sum = 0f64;
+        loop {
+            let decoded = r.decode_into(&mut timestamps, &mut values);
+            sum += values[..decoded].iter().sum::<f64>();
+            if decoded < values.len() {
+                return sum;
+            }
+        }
+    });
+
+    // reading a whole stream in the widths used by a typical sample
+    let widths = [1u8, 1, 1, 7, 1, 1, 5, 6, 20];
+    let total_width: usize = widths.iter().map(|&width| width as usize).sum();
+    let reads = w.len() / total_width;
+
+    bench("VecReader::read", reads, || {
+        let mut r = VecReader::new(&w.bit_vector, w.used_bits_last_elm);
+        (0..reads).fold(0, |acc, _| widths.iter().fold(acc, |acc, &width| acc ^ r.read(width).unwrap()))
+    });
+
+    bench("BufferedVecReader::read", reads, || {
+        let mut r = BufferedVecReader::new(&w.bit_vector, w.used_bits_last_elm);
+        (0..reads).fold(0, |acc, _| widths.iter().fold(acc, |acc, &width| acc ^ r.read(width).unwrap()))
+    });
+}
+
+fn main() {
+    run("test_data.csv", &csv_samples());
+    run("synthetic", &synthetic_samples(1_000_000));
+}
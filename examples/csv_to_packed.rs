@@ -3,7 +3,6 @@ extern crate gibbon;
 extern crate time;
 
 use gibbon::*;
-use std::{f64, u64};
 use std::cmp;
 
 struct TimeAndValue {
@@ -23,7 +22,7 @@ fn main() {
     for record in rdr.decode() {
         let (timestamp, value): (u64, f64) = record.unwrap();
         c.push(timestamp, value, &mut w);
-        uncompressed.push(TimeAndValue { timestamp: timestamp, value: value });
+        uncompressed.push(TimeAndValue { timestamp, value });
     }
     let now = time::precise_time_ns();
     println!("Read data in {} ms", (now - start) as f64 / 1_000_000f64);
@@ -67,12 +66,39 @@ fn main() {
     println!(" ({} ms)", (now - start) as f64 / 1_000_000f64);
     start = now;
 
+    {
+        let mut i = TimeAndValueIterator::new(vec_stream::VecReader::new(&w.bit_vector, w.used_bits_last_elm), header_time);
+        let mut timestamps = [0u64; 1024];
+        let mut values = [0f64; 1024];
+        let mut sum = 0f64;
+        loop {
+            let count = i.decode_into(&mut timestamps, &mut values);
+            sum += values[..count].iter().sum::<f64>();
+            if count < values.len() {
+                break;
+            }
+        }
+        print!("Average (decode_into): {}", sum / (samples as f64));
+    }
+    let now = time::precise_time_ns();
+    println!(" ({} ms)", (now - start) as f64 / 1_000_000f64);
+    start = now;
+
     {
         let i = TimeAndValueIterator::new(vec_stream::VecReader::new(&w.bit_vector, w.used_bits_last_elm), header_time);
         print!("Max timestamp: {}", i.map(|(timestamp, _value)| timestamp).fold(u64::MIN, cmp::max));
     }
     let now = time::precise_time_ns();
     println!(" ({} ms)", (now - start) as f64 / 1_000_000f64);
+    start = now;
+
+    {
+        let i = TimeAndValueIterator::new(vec_stream::VecReader::new(&w.bit_vector, w.used_bits_last_elm), header_time);
+        let hours = rollup(i, 3600).collect::<Vec<_>>();
+        print!("Hourly averages: {:?}", hours.iter().map(|hour| hour.mean()).collect::<Vec<_>>());
+    }
+    let now = time::precise_time_ns();
+    println!(" ({} ms)", (now - start) as f64 / 1_000_000f64);
 
     //------------------------------------------------------
 
@@ -88,7 +114,7 @@ fn main() {
     println!(" ({} ms)", (now - start) as f64 / 1_000_000f64);
     start = now;
 
-    print!("Samples: {}", uncompressed.iter().count());
+    print!("Samples: {}", uncompressed.len());
     let now = time::precise_time_ns();
     println!(" ({} ms)", (now - start) as f64 / 1_000_000f64);
     start = now;
@@ -0,0 +1,13 @@
+//! Reads arbitrary bytes as a block file, and decodes its samples if the
+//! header and checksum are valid. Either may fail, but must not panic.
+
+#![no_main]
+
+use gibbon::block_file::read_block;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(block) = read_block(&mut &data[..]) {
+        block.iter().count();
+    }
+});
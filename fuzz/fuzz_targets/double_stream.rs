@@ -0,0 +1,13 @@
+//! Decodes arbitrary bytes with both double codecs. Decoding may fail, but
+//! must not panic.
+
+#![no_main]
+
+use gibbon::slice_stream::SliceReader;
+use gibbon::*;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    DoubleStreamIterator::new(SliceReader::new(data, data.len() * 8)).count();
+    DoubleStreamLeadTrailIterator::new(SliceReader::new(data, data.len() * 8)).count();
+});
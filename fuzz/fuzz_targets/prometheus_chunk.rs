@@ -0,0 +1,11 @@
+//! Decodes arbitrary bytes as a Prometheus XOR chunk. Decoding may fail, but
+//! must not panic.
+
+#![no_main]
+
+use gibbon::prometheus_chunk::XorChunkIterator;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    XorChunkIterator::new(data).count();
+});
@@ -0,0 +1,30 @@
+//! Seeks in a stream with a restart index made of arbitrary bytes, which may
+//! point anywhere. Decoding may fail, but must not panic.
+
+#![no_main]
+
+use gibbon::vec_stream::{VecReader, VecWriter};
+use gibbon::*;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    if data.len() < 8 {
+        return;
+    }
+    let (target, index) = data.split_at(8);
+    let target = u64::from_be_bytes(target.try_into().unwrap());
+    let index = match RestartIndex::from_bytes(index) {
+        Some(index) => index,
+        None => return,
+    };
+
+    let mut w = VecWriter::new();
+    let mut c = TimeAndValueStream::new(0);
+    for i in 0..100 {
+        c.push(i * 10, i as f64, &mut w);
+    }
+
+    let mut r = TimeAndValueIterator::new(VecReader::new(&w.bit_vector, w.used_bits_last_elm), 0);
+    r.seek(&index, target);
+    r.count();
+});
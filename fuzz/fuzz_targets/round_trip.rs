@@ -0,0 +1,37 @@
+//! Encodes arbitrary bytes as doubles with every value codec, and checks that
+//! they decode to the same bits.
+
+#![no_main]
+
+use gibbon::prometheus_chunk::{XorChunkIterator, XorChunkWriter};
+use gibbon::vec_stream::{VecReader, VecWriter};
+use gibbon::*;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let values: Vec<u64> = data.chunks_exact(8).map(|chunk| u64::from_be_bytes(chunk.try_into().unwrap())).collect();
+
+    let mut w = VecWriter::new();
+    let mut c = DoubleStreamWriter::new();
+    for &value in &values {
+        c.push(f64::from_bits(value), &mut w);
+    }
+    let read: Vec<u64> = DoubleStreamIterator::new(VecReader::new(&w.bit_vector, w.used_bits_last_elm)).map(f64::to_bits).collect();
+    assert_eq!(read, values);
+
+    let mut w = VecWriter::new();
+    let mut c = DoubleStreamLeadTrail::new();
+    for &value in &values {
+        c.push(f64::from_bits(value), &mut w);
+    }
+    let read: Vec<u64> = DoubleStreamLeadTrailIterator::new(VecReader::new(&w.bit_vector, w.used_bits_last_elm)).map(f64::to_bits).collect();
+    assert_eq!(read, values);
+
+    let mut c = XorChunkWriter::new();
+    for (i, &value) in values.iter().take(prometheus_chunk::MAX_SAMPLES).enumerate() {
+        c.append(i as i64 * 15000, f64::from_bits(value));
+    }
+    let bytes = c.bytes();
+    let read: Vec<u64> = XorChunkIterator::new(&bytes).map(|(_, value)| value.to_bits()).collect();
+    assert_eq!(&read[..], &values[..values.len().min(prometheus_chunk::MAX_SAMPLES)]);
+});
@@ -0,0 +1,51 @@
+//! Decodes arbitrary bytes as a `TimeAndValueStream` with either value codec
+//! and every reader. Decoding may fail, but must not panic.
+
+#![no_main]
+
+use gibbon::slice_stream::SliceReader;
+use gibbon::vec_stream::{BufferedVecReader, VecReader};
+use gibbon::*;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    if data.len() < 8 {
+        return;
+    }
+    let (header, stream) = data.split_at(8);
+    let header_time = u64::from_be_bytes(header.try_into().unwrap());
+
+    TimeAndValueIterator::with_decoder(SliceReader::new(stream, stream.len() * 8), header_time, DoubleStreamLeadTrailParser::new()).count();
+
+    // the same bits as `stream`, with a partial last word left-aligned
+    let words: Vec<u64> = stream.chunks(8)
+        .map(|chunk| chunk.iter().fold(0, |word, &byte| word << 8 | byte as u64) << (64 - 8 * chunk.len()))
+        .collect();
+    let used_bits = (stream.len() % 8 * 8) as u8;
+    let used_bits = if used_bits == 0 && !words.is_empty() { 64 } else { used_bits };
+
+    let sliced: Vec<(u64, u64)> = TimeAndValueIterator::new(SliceReader::new(stream, stream.len() * 8), header_time)
+        .map(|(timestamp, value)| (timestamp, value.to_bits()))
+        .collect();
+    let iterated: Vec<(u64, u64)> = TimeAndValueIterator::new(VecReader::new(&words, used_bits), header_time)
+        .map(|(timestamp, value)| (timestamp, value.to_bits()))
+        .collect();
+    assert_eq!(sliced, iterated);
+    let buffered: Vec<(u64, u64)> = TimeAndValueIterator::new(BufferedVecReader::new(&words, used_bits), header_time)
+        .map(|(timestamp, value)| (timestamp, value.to_bits()))
+        .collect();
+    assert_eq!(iterated, buffered);
+
+    let mut r = TimeAndValueIterator::new(VecReader::new(&words, used_bits), header_time);
+    let mut timestamps = [0u64; 64];
+    let mut values = [0f64; 64];
+    let mut decoded = Vec::new();
+    loop {
+        let count = r.decode_into(&mut timestamps, &mut values);
+        decoded.extend(timestamps[..count].iter().zip(values[..count].iter()).map(|(&timestamp, value)| (timestamp, value.to_bits())));
+        if count < values.len() {
+            break;
+        }
+    }
+    assert_eq!(iterated, decoded);
+});
@@ -0,0 +1,27 @@
+//! Decodes arbitrary bytes as a timestamp stream, in a format picked by the
+//! first byte. Decoding may fail, but must not panic.
+
+#![no_main]
+
+use gibbon::slice_stream::SliceReader;
+use gibbon::*;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    if data.len() < 9 {
+        return;
+    }
+    let (header, stream) = data.split_at(9);
+    let version = if header[0] & 1 == 0 { TimestampVersion::V1 } else { TimestampVersion::V2 };
+    let precision = match header[0] >> 1 & 3 {
+        0 => TimestampPrecision::Seconds,
+        1 => TimestampPrecision::Milliseconds,
+        2 => TimestampPrecision::Microseconds,
+        _ => TimestampPrecision::Nanoseconds,
+    };
+    let table = if header[0] & 8 == 0 { None } else { DodTable::new([header[0] >> 4 & 7, 9, 12]) };
+    let format = TimestampFormat { version, precision, table };
+    let header_time = u64::from_be_bytes(header[1..].try_into().unwrap());
+
+    TimestampStreamIterator::with_format(SliceReader::new(stream, stream.len() * 8), header_time, format).count();
+});
@@ -0,0 +1,12 @@
+//! Decodes arbitrary bytes as a go-tsz series. Decoding may fail, but must
+//! not panic.
+
+#![no_main]
+
+use gibbon::slice_stream::SliceReader;
+use gibbon::tsz::TszIterator;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    TszIterator::new(SliceReader::new(data, data.len() * 8)).count();
+});
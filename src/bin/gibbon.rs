@@ -0,0 +1,66 @@
+//! Command line tools for gibbon files.
+//!
+//!     gibbon dump FILE...
+//!
+//! prints every sample of the block files given, with the bits the encoder
+//! emitted for it. See `gibbon::dump`.
+
+extern crate gibbon;
+
+use std::env;
+use std::fs::File;
+use std::io::{self, BufReader, Write};
+use std::process;
+
+use gibbon::block_file::{self, ValueCodec};
+use gibbon::dump::DumpIterator;
+use gibbon::vec_stream::VecReader;
+
+const USAGE: &str = "usage: gibbon dump FILE...";
+
+fn dump(path: &str, out: &mut dyn Write) -> Result<(), String> {
+    let file = File::open(path).map_err(|error| format!("{}: {}", path, error))?;
+    let block = block_file::read_block(&mut BufReader::new(file)).map_err(|error| format!("{}: {}", path, error))?;
+    if block.codec != ValueCodec::DoubleStream {
+        return Err(format!("{}: can only dump blocks using the DoubleStream codec, not {:?}", path, block.codec));
+    }
+
+    let write_error = |error: io::Error| error.to_string();
+    writeln!(out, "{}: header time {}, {} samples, {} bits, {:?}", path, block.header_time, block.count, block.data.len(), block.format).map_err(write_error)?;
+
+    let reader = VecReader::new(&block.data.bit_vector, block.data.used_bits_last_elm);
+    let mut records = DumpIterator::with_format(reader, block.header_time, block.format);
+    for record in records.by_ref() {
+        writeln!(out, "{}", record).map_err(write_error)?;
+    }
+
+    match records.error() {
+        Some(error) => Err(format!("{}: {}", path, error)),
+        None => Ok(()),
+    }
+}
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let paths = match args.split_first() {
+        Some((command, paths)) if command == "dump" && !paths.is_empty() => paths,
+        _ => {
+            eprintln!("{}", USAGE);
+            process::exit(2);
+        }
+    };
+
+    let stdout = io::stdout();
+    let mut out = stdout.lock();
+    let mut failed = false;
+    for path in paths {
+        if let Err(error) = dump(path, &mut out) {
+            eprintln!("gibbon: {}", error);
+            failed = true;
+        }
+    }
+
+    if failed {
+        process::exit(1);
+    }
+}
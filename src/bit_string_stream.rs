@@ -22,7 +22,7 @@ impl StringWriter {
 impl StringReader {
     pub fn new(string: String) -> Self {
         StringReader {
-            string: string,
+            string,
             position: 0,
         }
     }
@@ -34,6 +34,10 @@ impl Writer for StringWriter {
         assert_eq!(formatted.len(), count as usize);
         self.string.push_str(formatted);
     }
+
+    fn position(&self) -> usize {
+        self.string.len()
+    }
 }
 
 impl Reader for StringReader {
@@ -50,5 +54,9 @@ impl Reader for StringReader {
             None
         }
     }
+
+    fn position(&self) -> usize {
+        self.position
+    }
 }
 
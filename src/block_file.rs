@@ -0,0 +1,380 @@
+//! A file format for storing and shipping a compressed block. All numbers are
+//! big-endian:
+//!
+//! | bytes | field                                        |
+//! |-------|----------------------------------------------|
+//! | 4     | magic bytes, `GBBN`                          |
+//! | 1     | format version, currently 1                  |
+//! | 1     | value codec, see `ValueCodec`                |
+//! | 1     | timestamp version, see `TimestampVersion`    |
+//! | 1     | timestamp precision, see `TimestampPrecision`|
+//! | 3     | delta of delta bucket widths, or zeros       |
+//! | 8     | header time                                  |
+//! | 8     | number of samples                            |
+//! | 8     | length of the payload in bits                |
+//! | 4     | CRC32 (IEEE) of the payload                  |
+//! | ...   | payload, the bits of a `TimeAndValueStream`  |
+//!
+//! The timestamp fields hold the `TimestampFormat` of the payload, with the
+//! bucket widths all zero when the format has no explicit `DodTable`.
+//!
+//! The payload is laid out as by `VecWriter::to_bytes`, padded with zeros to a
+//! whole number of bytes, and can be read in place with a `SliceReader`.
+
+use std::io::{self, Read, Write};
+
+use double_stream::DoubleStreamParser;
+use double_stream_lead_trail::DoubleStreamLeadTrailParser;
+use dod_table::DodTable;
+use error::BlockError;
+use time_and_value_stream::TimeAndValueIterator;
+use timestamp_stream::{TimestampFormat, TimestampPrecision, TimestampStreamParser, TimestampVersion};
+use vec_stream::{VecWriter, VecReader};
+
+pub const MAGIC: [u8; 4] = *b"GBBN";
+pub const VERSION: u8 = 1;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ValueCodec {
+    /// `DoubleStreamWriter`
+    DoubleStream,
+    /// `DoubleStreamLeadTrail`
+    LeadTrail,
+}
+
+impl ValueCodec {
+    pub fn id(&self) -> u8 {
+        match *self {
+            ValueCodec::DoubleStream => 0,
+            ValueCodec::LeadTrail => 1,
+        }
+    }
+
+    pub fn from_id(id: u8) -> Option<Self> {
+        match id {
+            0 => Some(ValueCodec::DoubleStream),
+            1 => Some(ValueCodec::LeadTrail),
+            _ => None,
+        }
+    }
+}
+
+pub struct BlockFile {
+    pub codec: ValueCodec,
+    pub format: TimestampFormat,
+    pub header_time: u64,
+    pub count: u64,
+    pub data: VecWriter,
+}
+
+impl BlockFile {
+    /// Returns an error unless the block was written with `expected`, such as
+    /// before adding it to a series of that format.
+    pub fn check_format(&self, expected: TimestampFormat) -> Result<(), BlockError> {
+        if self.format == expected {
+            Ok(())
+        } else {
+            Err(BlockError::FormatMismatch { expected, actual: self.format })
+        }
+    }
+
+    pub fn iter<'a>(&'a self) -> Box<dyn Iterator<Item = (u64, f64)> + 'a> {
+        let reader = VecReader::new(&self.data.bit_vector, self.data.used_bits_last_elm);
+        let timestamps = TimestampStreamParser::with_format(self.header_time, self.format);
+        match self.codec {
+            ValueCodec::DoubleStream =>
+                Box::new(TimeAndValueIterator::from_parts(reader, timestamps, DoubleStreamParser::new())),
+            ValueCodec::LeadTrail =>
+                Box::new(TimeAndValueIterator::from_parts(reader, timestamps, DoubleStreamLeadTrailParser::new())),
+        }
+    }
+}
+
+pub fn write_block<W: Write>(writer: &mut W, block: &BlockFile) -> io::Result<()> {
+    let bit_length = block.data.len() as u64;
+    let payload = block.data.to_bytes();
+    let table = block.format.table.map_or([0; 3], |table| table.bucket_bits());
+
+    writer.write_all(&MAGIC)?;
+    writer.write_all(&[VERSION, block.codec.id(), block.format.version.id(), block.format.precision.id()])?;
+    writer.write_all(&table)?;
+    writer.write_all(&block.header_time.to_be_bytes())?;
+    writer.write_all(&block.count.to_be_bytes())?;
+    writer.write_all(&bit_length.to_be_bytes())?;
+    writer.write_all(&crc32(&payload).to_be_bytes())?;
+    writer.write_all(&payload)
+}
+
+pub fn read_block<R: Read>(reader: &mut R) -> Result<BlockFile, BlockError> {
+    let mut magic = [0u8; 4];
+    reader.read_exact(&mut magic)?;
+    if magic != MAGIC {
+        return Err(BlockError::BadMagic);
+    }
+
+    let mut version_and_codec = [0u8; 2];
+    reader.read_exact(&mut version_and_codec)?;
+    if version_and_codec[0] != VERSION {
+        return Err(BlockError::UnsupportedVersion(version_and_codec[0]));
+    }
+    let format = read_format(reader)?;
+    let codec = ValueCodec::from_id(version_and_codec[1])
+        .ok_or(BlockError::UnknownCodec(version_and_codec[1]))?;
+
+    let header_time = read_u64(reader)?;
+    let count = read_u64(reader)?;
+    let bit_length = read_u64(reader)?;
+    let mut checksum = [0u8; 4];
+    reader.read_exact(&mut checksum)?;
+    let expected = u32::from_be_bytes(checksum);
+
+    let byte_length = bit_length.div_ceil(8);
+    let mut payload = Vec::new();
+    reader.take(byte_length).read_to_end(&mut payload)?;
+    if (payload.len() as u64) < byte_length {
+        return Err(BlockError::Io(io::ErrorKind::UnexpectedEof.into()));
+    }
+
+    let actual = crc32(&payload);
+    if actual != expected {
+        return Err(BlockError::ChecksumMismatch { expected, actual });
+    }
+
+    Ok(BlockFile {
+        codec,
+        format,
+        header_time,
+        count,
+        data: from_payload_bytes(&payload, bit_length as usize),
+    })
+}
+
+fn read_format<R: Read>(reader: &mut R) -> Result<TimestampFormat, BlockError> {
+    let mut bytes = [0u8; 5];
+    reader.read_exact(&mut bytes)?;
+
+    let version = TimestampVersion::from_id(bytes[0]).ok_or(BlockError::UnknownTimestampVersion(bytes[0]))?;
+    let precision = TimestampPrecision::from_id(bytes[1]).ok_or(BlockError::UnknownPrecision(bytes[1]))?;
+    let bucket_bits = [bytes[2], bytes[3], bytes[4]];
+    let table = match bucket_bits {
+        [0, 0, 0] => None,
+        _ => Some(DodTable::new(bucket_bits).ok_or(BlockError::InvalidDodTable(bucket_bits))?),
+    };
+
+    Ok(TimestampFormat { version, precision, table })
+}
+
+fn read_u64<R: Read>(reader: &mut R) -> io::Result<u64> {
+    let mut bytes = [0u8; 8];
+    reader.read_exact(&mut bytes)?;
+    Ok(u64::from_be_bytes(bytes))
+}
+
+fn from_payload_bytes(bytes: &[u8], bit_length: usize) -> VecWriter {
+    if bit_length == 0 {
+        return VecWriter::new();
+    }
+
+    let bit_vector = bytes.chunks(8).map(|chunk| {
+        let mut word = [0u8; 8];
+        word[..chunk.len()].copy_from_slice(chunk);
+        u64::from_be_bytes(word)
+    }).collect::<Vec<u64>>();
+    let used_bits_last_elm = (bit_length - (bit_vector.len() - 1) * 64) as u8;
+
+    VecWriter {
+        bit_vector,
+        used_bits_last_elm,
+    }
+}
+
+const CRC32_TABLE: [u32; 256] = crc32_table();
+
+const fn crc32_table() -> [u32; 256] {
+    let mut table = [0u32; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 == 1 { (crc >> 1) ^ 0xEDB88320 } else { crc >> 1 };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+}
+
+/// CRC32 with the IEEE polynomial, as used by zlib and gzip.
+fn crc32(bytes: &[u8]) -> u32 {
+    !bytes.iter().fold(!0u32, |crc, &byte| CRC32_TABLE[((crc ^ byte as u32) & 0xFF) as usize] ^ (crc >> 8))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use time_and_value_stream::TimeAndValueStream;
+    use timestamp_stream::TimestampStreamWriter;
+    use double_stream::DoubleStreamWriter;
+    use double_stream_lead_trail::DoubleStreamLeadTrail;
+    use stream::Writer;
+    use std::io::Cursor;
+
+    const SAMPLES: [(u64, f64); 5] = [(10005, 0.34), (10065, 0.35), (10124, 0.72), (10247, 0.42), (10365, 1.12)];
+
+    fn block(codec: ValueCodec) -> BlockFile {
+        let mut data = VecWriter::new();
+        match codec {
+            ValueCodec::DoubleStream => {
+                let mut c = TimeAndValueStream::new(10000);
+                for &(timestamp, value) in SAMPLES.iter() {
+                    c.push(timestamp, value, &mut data);
+                }
+            },
+            ValueCodec::LeadTrail => {
+                let mut c = TimeAndValueStream::with_encoder(10000, DoubleStreamLeadTrail::new());
+                for &(timestamp, value) in SAMPLES.iter() {
+                    c.push(timestamp, value, &mut data);
+                }
+            },
+        }
+
+        BlockFile { codec, format: TimestampFormat::default(), header_time: 10000, count: SAMPLES.len() as u64, data }
+    }
+
+    fn written(block: &BlockFile) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        write_block(&mut bytes, block).unwrap();
+        bytes
+    }
+
+    #[test]
+    fn round_trip() {
+        for &codec in [ValueCodec::DoubleStream, ValueCodec::LeadTrail].iter() {
+            let original = block(codec);
+            let bytes = written(&original);
+            assert_eq!(bytes.len(), 39 + original.data.len().div_ceil(8));
+
+            let read = read_block(&mut Cursor::new(bytes)).unwrap();
+            assert_eq!(read.codec, codec);
+            assert_eq!(read.format, TimestampFormat::default());
+            assert_eq!(read.header_time, 10000);
+            assert_eq!(read.count, 5);
+            assert_eq!(read.data.len(), original.data.len());
+            assert_eq!(read.iter().collect::<Vec<_>>(), SAMPLES.to_vec());
+        }
+    }
+
+    #[test]
+    fn empty_block() {
+        let original = BlockFile { codec: ValueCodec::DoubleStream, format: TimestampFormat::default(), header_time: 0, count: 0, data: VecWriter::new() };
+        let read = read_block(&mut Cursor::new(written(&original))).unwrap();
+        assert_eq!(read.data.len(), 0);
+        assert_eq!(read.iter().next(), None);
+    }
+
+    #[test]
+    fn aligned_payload() {
+        let mut data = VecWriter::new();
+        data.write(0x0123456789ABCDEF, 64);
+        let original = BlockFile { codec: ValueCodec::DoubleStream, format: TimestampFormat::default(), header_time: 0, count: 0, data };
+        let read = read_block(&mut Cursor::new(written(&original))).unwrap();
+        assert_eq!(read.data.bit_vector, vec![0x0123456789ABCDEF]);
+        assert_eq!(read.data.used_bits_last_elm, 64);
+    }
+
+    #[test]
+    fn rejects_corrupt_files() {
+        let bytes = written(&block(ValueCodec::DoubleStream));
+
+        let mut bad_magic = bytes.clone();
+        bad_magic[0] = b'X';
+        match read_block(&mut Cursor::new(bad_magic)) {
+            Err(BlockError::BadMagic) => {},
+            _ => panic!("expected bad magic"),
+        }
+
+        let mut bad_version = bytes.clone();
+        bad_version[4] = 2;
+        match read_block(&mut Cursor::new(bad_version)) {
+            Err(BlockError::UnsupportedVersion(2)) => {},
+            _ => panic!("expected unsupported version"),
+        }
+
+        let mut bad_codec = bytes.clone();
+        bad_codec[5] = 9;
+        match read_block(&mut Cursor::new(bad_codec)) {
+            Err(BlockError::UnknownCodec(9)) => {},
+            _ => panic!("expected unknown codec"),
+        }
+
+        let mut bad_timestamp_version = bytes.clone();
+        bad_timestamp_version[6] = 0;
+        match read_block(&mut Cursor::new(bad_timestamp_version)) {
+            Err(BlockError::UnknownTimestampVersion(0)) => {},
+            _ => panic!("expected unknown timestamp version"),
+        }
+
+        let mut bad_precision = bytes.clone();
+        bad_precision[7] = 4;
+        match read_block(&mut Cursor::new(bad_precision)) {
+            Err(BlockError::UnknownPrecision(4)) => {},
+            _ => panic!("expected unknown precision"),
+        }
+
+        let mut bad_table = bytes.clone();
+        bad_table[8..11].copy_from_slice(&[9, 7, 12]);
+        match read_block(&mut Cursor::new(bad_table)) {
+            Err(BlockError::InvalidDodTable([9, 7, 12])) => {},
+            _ => panic!("expected invalid table"),
+        }
+
+        let mut flipped = bytes.clone();
+        *flipped.last_mut().unwrap() ^= 0x80;
+        match read_block(&mut Cursor::new(flipped)) {
+            Err(BlockError::ChecksumMismatch { .. }) => {},
+            _ => panic!("expected checksum mismatch"),
+        }
+
+        let truncated = bytes[..bytes.len() - 1].to_vec();
+        match read_block(&mut Cursor::new(truncated)) {
+            Err(BlockError::Io(ref error)) if error.kind() == io::ErrorKind::UnexpectedEof => {},
+            _ => panic!("expected unexpected end of file"),
+        }
+    }
+
+    #[test]
+    fn timestamp_formats() {
+        let formats = [
+            TimestampFormat { version: TimestampVersion::V2, ..Default::default() },
+            TimestampFormat { precision: TimestampPrecision::Milliseconds, ..Default::default() },
+            TimestampFormat { table: DodTable::new([3, 10, 20]), ..Default::default() },
+        ];
+        let samples = [(1000, 1.0), (1010, 2.0), (1021, 3.0), (1030 + (1 << 40), 4.0)];
+
+        for &format in formats.iter() {
+            let mut data = VecWriter::new();
+            let mut c = TimeAndValueStream::from_parts(TimestampStreamWriter::with_format(1000, format), DoubleStreamWriter::new());
+            let pushed: Vec<(u64, f64)> = samples.iter().cloned().take_while(|&(timestamp, value)| c.try_push(timestamp, value, &mut data).is_ok()).collect();
+            assert!(pushed.len() >= 3);
+
+            let original = BlockFile { codec: ValueCodec::DoubleStream, format, header_time: 1000, count: pushed.len() as u64, data };
+            let read = read_block(&mut Cursor::new(written(&original))).unwrap();
+            assert_eq!(read.format, format, "{:?}", format);
+            assert_eq!(read.iter().collect::<Vec<_>>(), pushed, "{:?}", format);
+
+            assert!(read.check_format(format).is_ok());
+            match read.check_format(TimestampFormat::default()) {
+                Err(BlockError::FormatMismatch { expected, actual }) => assert_eq!((expected, actual), (TimestampFormat::default(), format)),
+                _ => panic!("expected format mismatch"),
+            }
+        }
+    }
+
+    #[test]
+    fn crc32_check_value() {
+        assert_eq!(crc32(b"123456789"), 0xCBF43926);
+        assert_eq!(crc32(b""), 0);
+    }
+}
@@ -0,0 +1,174 @@
+//! The bucket table used by a timestamp stream to store delta of deltas.
+//!
+//! A delta of deltas of zero is stored as a single `0` bit. Other values are
+//! stored in the first of three buckets they fit in, prefixed with `10`, `110`
+//! and `1110` respectively. A bucket of `n` bits stores the range
+//! `-(2^(n-1) - 1)..=2^(n-1)`. Anything else is prefixed with `1111` and stored
+//! in 32 or 64 bits, depending on the `TimestampVersion`.
+//!
+//! The Gorilla paper uses buckets of 7, 9 and 12 bits, which suits timestamps
+//! in seconds at regular intervals. Jittery intervals compress better with
+//! wider buckets, and `DodTable::from_sample` finds the best table for a
+//! sample of timestamps.
+
+use timestamp_stream::TimestampPrecision;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DodTable {
+    bucket_bits: [u8; 3],
+}
+
+impl DodTable {
+    /// Returns `None` unless the bucket widths are strictly increasing and
+    /// between 1 and 63 bits.
+    pub fn new(bucket_bits: [u8; 3]) -> Option<Self> {
+        let [a, b, c] = bucket_bits;
+        if a >= 1 && a < b && b < c && c <= 63 {
+            Some(DodTable { bucket_bits })
+        } else {
+            None
+        }
+    }
+
+    /// The table from the paper, with the widths scaled to `precision`.
+    pub fn for_precision(precision: TimestampPrecision) -> Self {
+        let extra = precision.extra_bits();
+        DodTable { bucket_bits: [7 + extra, 9 + extra, 12 + extra] }
+    }
+
+    /// Finds the table that stores the delta of deltas of `timestamps` in the
+    /// fewest bits, when those that don't fit in any bucket take `escape_bits`,
+    /// see `TimestampFormat::escape_bits`. Falls back to the default table if
+    /// no table does better.
+    pub fn from_sample(timestamps: &[u64], escape_bits: u8) -> Self {
+        // histogram of the number of bits needed for each delta of deltas
+        let mut counts = [0u64; 65];
+        for window in timestamps.windows(3) {
+            if window[1] < window[0] || window[2] < window[1] {
+                continue;
+            }
+            let delta_of_deltas = ((window[2] - window[1]) as i64).wrapping_sub((window[1] - window[0]) as i64);
+            if delta_of_deltas != 0 {
+                counts[bits_needed(delta_of_deltas) as usize] += 1;
+            }
+        }
+
+        let cost = |table: &DodTable| -> u64 {
+            counts.iter().enumerate().skip(1).map(|(bits, &count)| {
+                let encoded = match table.bucket_bits.iter().position(|&width| bits <= width as usize) {
+                    Some(bucket) => bucket as u64 + 2 + table.bucket_bits[bucket] as u64,
+                    None => 4 + escape_bits as u64,
+                };
+                count * encoded
+            }).sum()
+        };
+
+        let mut best = DodTable::default();
+        let mut best_cost = cost(&best);
+        for a in 1..62 {
+            for b in a + 1..63 {
+                for c in b + 1..64 {
+                    let table = DodTable { bucket_bits: [a, b, c] };
+                    let table_cost = cost(&table);
+                    if table_cost < best_cost {
+                        best = table;
+                        best_cost = table_cost;
+                    }
+                }
+            }
+        }
+
+        best
+    }
+
+    /// The widths of the buckets with the `10`, `110` and `1110` prefixes.
+    pub fn bucket_bits(&self) -> [u8; 3] {
+        self.bucket_bits
+    }
+
+    /// The first bucket that can store `delta_of_deltas`, if any.
+    pub fn bucket_for(&self, delta_of_deltas: i64) -> Option<usize> {
+        self.bucket_bits.iter().position(|&bits| (-bias(bits)..=bias(bits) + 1).contains(&delta_of_deltas))
+    }
+}
+
+impl Default for DodTable {
+    fn default() -> Self {
+        DodTable::for_precision(TimestampPrecision::Seconds)
+    }
+}
+
+/// The bias added to a delta of deltas stored in `bits` bits, which is stored
+/// as a non-negative number.
+pub fn bias(bits: u8) -> i64 {
+    (1i64 << (bits - 1)) - 1
+}
+
+/// The width of the smallest bucket that can store `delta_of_deltas`, or 64 if
+/// it only fits in the escape.
+fn bits_needed(delta_of_deltas: i64) -> u8 {
+    (1..64).find(|&bits| (-bias(bits)..=bias(bits) + 1).contains(&delta_of_deltas)).unwrap_or(64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn validation() {
+        assert!(DodTable::new([7, 9, 12]).is_some());
+        assert!(DodTable::new([1, 2, 63]).is_some());
+        assert!(DodTable::new([0, 9, 12]).is_none());
+        assert!(DodTable::new([9, 9, 12]).is_none());
+        assert!(DodTable::new([7, 9, 64]).is_none());
+    }
+
+    #[test]
+    fn buckets() {
+        let table = DodTable::default();
+        assert_eq!(table.bucket_for(-63), Some(0));
+        assert_eq!(table.bucket_for(64), Some(0));
+        assert_eq!(table.bucket_for(-64), Some(1));
+        assert_eq!(table.bucket_for(256), Some(1));
+        assert_eq!(table.bucket_for(2048), Some(2));
+        assert_eq!(table.bucket_for(2049), None);
+    }
+
+    #[test]
+    fn bits_needed_matches_buckets() {
+        assert_eq!(bits_needed(1), 1);
+        assert_eq!(bits_needed(-1), 2);
+        assert_eq!(bits_needed(64), 7);
+        assert_eq!(bits_needed(-64), 8);
+        assert_eq!(bits_needed(i64::MIN), 64);
+    }
+
+    #[test]
+    fn from_sample() {
+        // regular intervals have nothing to gain
+        let regular: Vec<u64> = (0..100).map(|i| i * 10).collect();
+        assert_eq!(DodTable::from_sample(&regular, 32), DodTable::default());
+
+        // 10 second intervals in milliseconds, with up to 200 ms of jitter
+        let jittery: Vec<u64> = (0..1_000u64).map(|i| i * 10_000 + (i * 7919) % 200).collect();
+        let table = DodTable::from_sample(&jittery, 32);
+        assert_eq!(table.bucket_bits()[0], 9);
+    }
+
+    #[test]
+    fn from_sample_escape_bits() {
+        // mostly small delta of deltas, and a few around 2^28 that are worth a
+        // bucket of their own only when the escape takes 64 bits
+        let mut timestamps = vec![0, 1 << 30];
+        for &(delta_of_deltas, count) in [(1i64, 100), (40, 100), (1500, 100), (1 << 28, 20)].iter() {
+            for &dod in [delta_of_deltas, -delta_of_deltas].iter().cycle().take(2 * count) {
+                let n = timestamps.len();
+                let delta = (timestamps[n - 1] - timestamps[n - 2]) as i64 + dod;
+                timestamps.push(timestamps[n - 1] + delta as u64);
+            }
+        }
+
+        assert_eq!(DodTable::from_sample(&timestamps, 32).bucket_bits(), [2, 7, 12]);
+        assert_eq!(DodTable::from_sample(&timestamps, 64).bucket_bits(), [7, 12, 30]);
+    }
+}
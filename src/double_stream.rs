@@ -8,9 +8,14 @@
 //! trailing zeroes are available. See `DoubleStreamLeadTrail` for an
 //! implementation of the latter.
 
-pub use stream::{Writer, Reader};
+pub use stream::{Writer, Reader, ValueEncoder, ValueDecoder};
+pub use error::DecodeError;
+use error::{read_control, read_payload};
 use std::cmp::min;
+use vec_stream::VecReader;
+use stream::SeekableReader;
 
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum DoubleStreamState {
     Initial,
     Following {
@@ -30,7 +35,13 @@ impl DoubleStreamWriter {
         }
     }
 
-    pub fn push(&mut self, number: f64, writer: &mut Writer) {
+    /// The state after the last pushed value, for resuming decoding there. See
+    /// `RestartIndex`.
+    pub fn state(&self) -> DoubleStreamState {
+        self.state
+    }
+
+    pub fn push(&mut self, number: f64, writer: &mut dyn Writer) {
         let number_as_bits = number.to_bits();
 
         self.state = match self.state {
@@ -82,6 +93,18 @@ impl DoubleStreamWriter {
     }
 }
 
+impl Default for DoubleStreamWriter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ValueEncoder for DoubleStreamWriter {
+    fn push(&mut self, number: f64, writer: &mut dyn Writer) {
+        DoubleStreamWriter::push(self, number, writer)
+    }
+}
+
 pub struct DoubleStreamParser {
     state: DoubleStreamState,
 }
@@ -93,73 +116,159 @@ impl DoubleStreamParser {
         }
     }
 
-    pub fn next(&mut self, reader: &mut Reader) -> Option<f64> {
-        let values = match self.state {
+    pub fn state(&self) -> DoubleStreamState {
+        self.state
+    }
+
+    /// Continues decoding from `state`, which the reader must be positioned
+    /// after.
+    pub fn set_state(&mut self, state: DoubleStreamState) {
+        self.state = state;
+    }
+
+    /// Returns `Ok(None)` at the end of the stream, or an error if the stream
+    /// ends in the middle of a value or is otherwise malformed.
+    pub fn next<R: Reader + ?Sized>(&mut self, reader: &mut R) -> Result<Option<f64>, DecodeError> {
+        let (value, xor) = match self.state {
             DoubleStreamState::Initial => {
-                reader.read(64).and_then(|x| Some((x, x)))
+                match reader.read(64) {
+                    Some(x) => (x, x),
+                    None => return Ok(None),
+                }
             }
             DoubleStreamState::Following { value, xor } => {
+                let offset = reader.position();
                 match reader.read(1) {
-                    Some(0) => Some((value, xor)),
-                    Some(1) => {
-                        // unwrapping reads from now on, on the assumption that the stream is
-                        // well-formed
-                        match reader.read(1).unwrap() {
-                            0 => { // reuse window
-                                let prev_lz = xor.leading_zeros() as u64;
-                                let prev_tz = if prev_lz == 64 { 0 } else { xor.trailing_zeros() as u64 };
-                                let meaningful_bit_count = 64 - prev_tz - prev_lz;
-
-                                let new_xor = reader.read(meaningful_bit_count as u8).unwrap() << prev_tz;
-                                let new_value = value ^ new_xor;
-                                Some((new_value, new_xor))
-                            },
-                            1 => { // new window
-                                let lz = reader.read(5).unwrap();
-                                let meaningful_bit_count = reader.read(6).unwrap() + 1;
-                                let tz = 64 - meaningful_bit_count - lz;
-
-                                let new_xor = reader.read(meaningful_bit_count as u8).unwrap() << tz;
-                                let new_value = value ^ new_xor;
-                                Some((new_value, new_xor))
-                            },
-                            _ => panic!("Binary read should not be able to return anything but 0 or 1")
+                    None => return Ok(None),
+                    Some(0) => (value, xor),
+                    Some(_) => {
+                        if read_control(reader, 1)? == 0 { // reuse window
+                            let prev_lz = xor.leading_zeros() as u64;
+                            let prev_tz = if prev_lz == 64 { 0 } else { xor.trailing_zeros() as u64 };
+                            let meaningful_bit_count = 64 - prev_tz - prev_lz;
+
+                            if meaningful_bit_count == 0 {
+                                // no meaningful bits have been seen yet, so the window is empty; a valid writer never reuses it
+                                return Err(DecodeError::InvalidWindow { offset, leading_zeros: prev_lz as u8, meaningful_bits: 0 });
+                            }
+
+                            let new_xor = read_payload(reader, meaningful_bit_count as u8)? << prev_tz;
+                            (value ^ new_xor, new_xor)
+                        } else { // new window
+                            let lz = read_payload(reader, 5)?;
+                            let meaningful_bit_count = read_payload(reader, 6)? + 1;
+
+                            if lz + meaningful_bit_count > 64 {
+                                return Err(DecodeError::InvalidWindow { offset, leading_zeros: lz as u8, meaningful_bits: meaningful_bit_count as u8 });
+                            }
+
+                            let tz = 64 - meaningful_bit_count - lz;
+
+                            let new_xor = read_payload(reader, meaningful_bit_count as u8)? << tz;
+                            (value ^ new_xor, new_xor)
                         }
                     }
-                    None => None,
-                    _ => panic!("Binary read should not be able to return anything but 0 or 1")
                 }
             }
         };
 
-        if let Some((value, xor)) = values {
-            self.state = DoubleStreamState::Following { value: value, xor: xor };
-            Some(f64::from_bits(value))
-        } else {
-            None
+        self.state = DoubleStreamState::Following { value, xor };
+        Ok(Some(f64::from_bits(value)))
+    }
+
+    /// Decodes values into `out` until it is full or the stream ends, and
+    /// returns how many were decoded. See
+    /// `TimestampStreamParser::decode_into`.
+    pub fn decode_into<R: SeekableReader + ?Sized>(&mut self, reader: &mut R, out: &mut [f64]) -> Result<usize, DecodeError> {
+        for (count, slot) in out.iter_mut().enumerate() {
+            let position = reader.position();
+            match self.next(reader) {
+                Ok(Some(value)) => *slot = value,
+                Ok(None) => return Ok(count),
+                Err(error) => {
+                    reader.seek(position);
+                    return if count == 0 { Err(error) } else { Ok(count) };
+                }
+            }
         }
+        Ok(out.len())
+    }
+}
+
+impl Default for DoubleStreamParser {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ValueDecoder for DoubleStreamParser {
+    fn next(&mut self, reader: &mut dyn Reader) -> Result<Option<f64>, DecodeError> {
+        DoubleStreamParser::next(self, reader)
     }
 }
 
+/// Iterates over the values in a stream. Iteration stops at the first decode
+/// error, which is then available from `error`.
 pub struct DoubleStreamIterator<R: Reader> {
     parser: DoubleStreamParser,
     reader: R,
+    error: Option<DecodeError>,
 }
 
 impl<R> DoubleStreamIterator<R> where R: Reader{
     pub fn new(reader: R) -> Self {
         DoubleStreamIterator {
             parser: DoubleStreamParser::new(),
-            reader: reader,
+            reader,
+            error: None,
         }
     }
+
+    pub fn error(&self) -> Option<&DecodeError> {
+        self.error.as_ref()
+    }
+}
+
+impl<'a> DoubleStreamIterator<VecReader<'a>> {
+    /// Decodes values into `out` until it is full or the stream ends, and
+    /// returns how many were decoded. See
+    /// `TimestampStreamIterator::decode_into`.
+    pub fn decode_into(&mut self, out: &mut [f64]) -> usize {
+        if self.error.is_some() {
+            return 0;
+        }
+
+        let mut buffered = self.reader.buffered();
+        let count = match self.parser.decode_into(&mut buffered, out) {
+            Ok(count) if count < out.len() => {
+                // the end of the stream, or an error after `count` values
+                self.error = self.parser.next(&mut buffered).err();
+                count
+            },
+            Ok(count) => count,
+            Err(error) => {
+                self.error = Some(error);
+                0
+            }
+        };
+
+        self.reader.seek(buffered.position());
+        count
+    }
 }
 
 impl<R> Iterator for DoubleStreamIterator<R> where R: Reader {
     type Item = f64;
 
     fn next(&mut self) -> Option<f64> {
-        self.parser.next(&mut self.reader)
+        if self.error.is_some() {
+            return None;
+        }
+
+        self.parser.next(&mut self.reader).unwrap_or_else(|error| {
+            self.error = Some(error);
+            None
+        })
     }
 }
 
@@ -167,7 +276,7 @@ impl<R> Iterator for DoubleStreamIterator<R> where R: Reader {
 mod tests {
     use super::*;
     use bit_string_stream::*;
-    use vec_stream::{VecWriter, VecReader};
+    use vec_stream::{VecWriter, VecReader, BufferedVecReader};
 
     #[test]
     fn all_zeros() {
@@ -308,7 +417,7 @@ mod tests {
     #[test]
     fn read_aligned_64() {
         // This test case triggers a read of 64 bits exactly aligned to the word boundry.
-        let case = vec![-75.01536474599993, -75.00911189799993, 114.37647545700004];
+        let case = [-75.01536474599993, -75.00911189799993, 114.37647545700004];
 
         let mut writer = VecWriter::new();
         let mut stream = DoubleStreamWriter::new();
@@ -327,4 +436,91 @@ mod tests {
         let read: Vec<f64> = reader.collect();
         assert_eq!(&read[..], &case[..]);
     }
+
+    #[test]
+    fn truncated_control() {
+        let mut r = StringReader::new(format!("{:064b}1", 0));
+        let mut p = DoubleStreamParser::new();
+        assert_eq!(p.next(&mut r), Ok(Some(0f64)));
+        assert_eq!(p.next(&mut r), Err(DecodeError::TruncatedControl { offset: 65 }));
+    }
+
+    #[test]
+    fn truncated_payload() {
+        let mut w = StringWriter::new();
+        let mut c = DoubleStreamWriter::new();
+        c.push(0f64, &mut w);
+        c.push(1f64, &mut w);
+
+        let length = w.string.len();
+        let mut r = DoubleStreamIterator::new(StringReader::new(w.string[..length - 3].to_string()));
+        assert_eq!(r.next(), Some(0f64));
+        assert_eq!(r.next(), None);
+        assert_eq!(r.error(), Some(&DecodeError::TruncatedPayload { offset: 64 + 2 + 5 + 6 }));
+        assert_eq!(r.next(), None);
+    }
+
+    #[test]
+    fn invalid_window() {
+        // 31 leading zeros and 64 meaningful bits
+        let mut r = StringReader::new(format!("{:064b}1111111111111", 0));
+        let mut p = DoubleStreamParser::new();
+        assert_eq!(p.next(&mut r), Ok(Some(0f64)));
+        assert_eq!(p.next(&mut r), Err(DecodeError::InvalidWindow { offset: 64, leading_zeros: 31, meaningful_bits: 64 }));
+
+        // reusing the window after a repeated value
+        let mut r = StringReader::new(format!("{:064b}0101", 0));
+        let mut p = DoubleStreamParser::new();
+        assert_eq!(p.next(&mut r), Ok(Some(0f64)));
+        assert_eq!(p.next(&mut r), Ok(Some(0f64)));
+        assert_eq!(p.next(&mut r), Err(DecodeError::InvalidWindow { offset: 65, leading_zeros: 64, meaningful_bits: 0 }));
+    }
+
+    #[test]
+    fn decode_into() {
+        let values: Vec<f64> = (0..200).map(|i| (i as f64 * 0.1).sin()).collect();
+        let mut w = VecWriter::new();
+        let mut c = DoubleStreamWriter::new();
+        for &value in values.iter() {
+            c.push(value, &mut w);
+        }
+
+        let mut r = DoubleStreamIterator::new(VecReader::new(&w.bit_vector, w.used_bits_last_elm));
+        let mut read = [0f64; 256];
+        assert_eq!(r.decode_into(&mut read[..150]), 150);
+        assert_eq!(r.decode_into(&mut read[150..]), 50);
+        assert_eq!(&read[..200], &values[..]);
+        assert_eq!(r.next(), None);
+        assert_eq!(r.error(), None);
+    }
+
+    #[test]
+    fn parser_decode_into() {
+        let mut w = VecWriter::new();
+        let mut c = DoubleStreamWriter::new();
+        for &value in [1.0, 2.0, 3.0].iter() {
+            c.push(value, &mut w);
+        }
+        let valid_bits = w.len();
+        // 11, then a new window of 31 leading zeros and 64 meaningful bits
+        w.write(0x1FFF, 13);
+
+        let mut r = BufferedVecReader::new(&w.bit_vector, w.used_bits_last_elm);
+        let mut p = DoubleStreamParser::new();
+        let mut read = [0f64; 4];
+        assert_eq!(p.decode_into(&mut r, &mut read[..2]), Ok(2));
+        assert_eq!(p.decode_into(&mut r, &mut read[2..]), Ok(1));
+        assert_eq!(&read[..3], &[1.0, 2.0, 3.0]);
+
+        // the error is returned by the next call, starting at the bad value
+        assert_eq!(r.position(), valid_bits);
+        assert_eq!(p.decode_into(&mut r, &mut read), Err(DecodeError::InvalidWindow { offset: valid_bits, leading_zeros: 31, meaningful_bits: 64 }));
+        assert_eq!(r.position(), valid_bits);
+
+        // while the iterator reports it right away
+        let mut r = DoubleStreamIterator::new(VecReader::new(&w.bit_vector, w.used_bits_last_elm));
+        assert_eq!(r.decode_into(&mut read), 3);
+        assert!(r.error().is_some());
+        assert_eq!(r.decode_into(&mut read), 0);
+    }
 }
@@ -5,9 +5,12 @@
 //! bits and meaningful bits to keep a non-shrinking window. The only time
 //! The window changes is for explict changes.
 
-pub use stream::{Writer, Reader};
+pub use stream::{Writer, Reader, ValueEncoder, ValueDecoder};
+pub use error::DecodeError;
+use error::{read_control, read_payload};
 use std::cmp::min;
 
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum DoubleStreamStateLeadTrail {
     Initial,
     Following {
@@ -32,7 +35,7 @@ impl DoubleStreamLeadTrail {
         }
     }
 
-    pub fn push(&mut self, number: f64, writer: &mut Writer) {
+    pub fn push(&mut self, number: f64, writer: &mut dyn Writer) {
         let number_as_bits = number.to_bits();
 
         self.state = match self.state {
@@ -71,7 +74,7 @@ impl DoubleStreamLeadTrail {
                             let meaningful_bit_count = 64 - prev_tz - prev_lz;
 
                             writer.write(0b10, 2);
-                            writer.write(meaningful_bits, meaningful_bit_count as u8);
+                            writer.write(meaningful_bits, meaningful_bit_count);
 
                             // keep window size
                             DoubleStreamStateLeadTrail::Following {
@@ -106,3 +109,235 @@ impl DoubleStreamLeadTrail {
         };
     }
 }
+
+impl Default for DoubleStreamLeadTrail {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ValueEncoder for DoubleStreamLeadTrail {
+    fn push(&mut self, number: f64, writer: &mut dyn Writer) {
+        DoubleStreamLeadTrail::push(self, number, writer)
+    }
+}
+
+pub struct DoubleStreamLeadTrailParser {
+    state: DoubleStreamStateLeadTrail,
+}
+
+impl DoubleStreamLeadTrailParser {
+    pub fn new() -> Self {
+        DoubleStreamLeadTrailParser {
+            state: DoubleStreamStateLeadTrail::Initial
+        }
+    }
+
+    /// Returns `Ok(None)` at the end of the stream, or an error if the stream
+    /// ends in the middle of a value or is otherwise malformed.
+    pub fn next(&mut self, reader: &mut dyn Reader) -> Result<Option<f64>, DecodeError> {
+        let (value, leading_zeros, meaningful_count) = match self.state {
+            DoubleStreamStateLeadTrail::Initial => {
+                match reader.read(64) {
+                    // force window to be redefined, same as the writer
+                    Some(x) => (x, 64, 0),
+                    None => return Ok(None),
+                }
+            }
+            DoubleStreamStateLeadTrail::Following { value, leading_zeros, meaningful_count } => {
+                let offset = reader.position();
+                match reader.read(1) {
+                    None => return Ok(None),
+                    Some(0) => (value, leading_zeros, meaningful_count),
+                    Some(_) => {
+                        if read_control(reader, 1)? == 0 { // reuse window
+                            if meaningful_count == 0 {
+                                // the writer always defines a window after the first value
+                                return Err(DecodeError::InvalidWindow { offset, leading_zeros, meaningful_bits: 0 });
+                            }
+
+                            let prev_tz = 64 - leading_zeros - meaningful_count;
+
+                            let new_xor = read_payload(reader, meaningful_count)? << prev_tz;
+                            (value ^ new_xor, leading_zeros, meaningful_count)
+                        } else { // new window
+                            let lz = read_payload(reader, 5)? as u8;
+                            let meaningful_bit_count = read_payload(reader, 6)? as u8 + 1;
+
+                            if lz + meaningful_bit_count > 64 {
+                                return Err(DecodeError::InvalidWindow { offset, leading_zeros: lz, meaningful_bits: meaningful_bit_count });
+                            }
+
+                            let tz = 64 - meaningful_bit_count - lz;
+
+                            let new_xor = read_payload(reader, meaningful_bit_count)? << tz;
+                            (value ^ new_xor, lz, meaningful_bit_count)
+                        }
+                    }
+                }
+            }
+        };
+
+        self.state = DoubleStreamStateLeadTrail::Following { value, leading_zeros, meaningful_count };
+        Ok(Some(f64::from_bits(value)))
+    }
+}
+
+impl Default for DoubleStreamLeadTrailParser {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ValueDecoder for DoubleStreamLeadTrailParser {
+    fn next(&mut self, reader: &mut dyn Reader) -> Result<Option<f64>, DecodeError> {
+        DoubleStreamLeadTrailParser::next(self, reader)
+    }
+}
+
+/// Iterates over the values in a stream. Iteration stops at the first decode
+/// error, which is then available from `error`.
+pub struct DoubleStreamLeadTrailIterator<R: Reader> {
+    parser: DoubleStreamLeadTrailParser,
+    reader: R,
+    error: Option<DecodeError>,
+}
+
+impl<R> DoubleStreamLeadTrailIterator<R> where R: Reader {
+    pub fn new(reader: R) -> Self {
+        DoubleStreamLeadTrailIterator {
+            parser: DoubleStreamLeadTrailParser::new(),
+            reader,
+            error: None,
+        }
+    }
+
+    pub fn error(&self) -> Option<&DecodeError> {
+        self.error.as_ref()
+    }
+}
+
+impl<R> Iterator for DoubleStreamLeadTrailIterator<R> where R: Reader {
+    type Item = f64;
+
+    fn next(&mut self) -> Option<f64> {
+        if self.error.is_some() {
+            return None;
+        }
+
+        self.parser.next(&mut self.reader).unwrap_or_else(|error| {
+            self.error = Some(error);
+            None
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bit_string_stream::*;
+    use vec_stream::{VecWriter, VecReader};
+
+    #[test]
+    fn all_zeros() {
+        // using XOR == 0 rule (0)
+        let mut w = StringWriter::new();
+        let mut c = DoubleStreamLeadTrail::new();
+        c.push(0f64, &mut w); assert_eq!(w.string, "0000000000000000000000000000000000000000000000000000000000000000");
+        c.push(0f64, &mut w); assert_eq!(w.string, "00000000000000000000000000000000000000000000000000000000000000000");
+        c.push(0f64, &mut w); assert_eq!(w.string, "000000000000000000000000000000000000000000000000000000000000000000");
+
+        let mut r = DoubleStreamLeadTrailIterator::new(StringReader::new(w.string));
+        assert_eq!(r.next(), Some(0f64));
+        assert_eq!(r.next(), Some(0f64));
+        assert_eq!(r.next(), Some(0f64));
+        assert_eq!(r.next(), None);
+    }
+
+    #[test]
+    fn new_window() {
+        // using "new window" rule (11)
+        let mut w = StringWriter::new();
+        let mut c = DoubleStreamLeadTrail::new();
+        c.push(0f64, &mut w); assert_eq!(w.string, "0000000000000000000000000000000000000000000000000000000000000000");
+        // one: 0011111111110000000000000000000000000000000000000000000000000000
+        // L = leading zeros, #M = number of meaningful bits, meanfbits = the meaningful bits themselves -->       11[ L ][#M-1][meanbits]
+        c.push(1f64, &mut w); assert_eq!(w.string, "000000000000000000000000000000000000000000000000000000000000000011000100010011111111111");
+
+        let mut r = DoubleStreamLeadTrailIterator::new(StringReader::new(w.string));
+        assert_eq!(r.next(), Some(0f64));
+        assert_eq!(r.next(), Some(1f64));
+        assert_eq!(r.next(), None);
+    }
+
+    #[test]
+    fn keep_window() {
+        // unlike `DoubleStream`, the window is kept after a narrower xor
+        // eleven: 0100000000100110000000000000000000000000000000000000000000000000
+        // ten:    0100000000100100000000000000000000000000000000000000000000000000
+        // twelve: 0100000000101000000000000000000000000000000000000000000000000000
+        let mut w = StringWriter::new();
+        let mut c = DoubleStreamLeadTrail::new();
+        c.push(11f64, &mut w);
+        c.push(10f64, &mut w); // new window: 11[01110][000000][1]
+        c.push(11f64, &mut w); // reuse:      10[1]
+        c.push(12f64, &mut w); // new window, xor with eleven is 0000000000001110...
+        assert_eq!(&w.string[64..], concat!("11011100000001", "101", "1101100000010111"));
+
+        let mut r = DoubleStreamLeadTrailIterator::new(StringReader::new(w.string));
+        assert_eq!(r.next(), Some(11f64));
+        assert_eq!(r.next(), Some(10f64));
+        assert_eq!(r.next(), Some(11f64));
+        assert_eq!(r.next(), Some(12f64));
+        assert_eq!(r.next(), None);
+    }
+
+    #[test]
+    fn all_significant_bits () {
+        let mut w = StringWriter::new();
+        let mut c = DoubleStreamLeadTrail::new();
+        let all_significant = f64::from_bits(0b1000000000000000000000000000000000000000000000000000000000000001u64);
+
+        c.push(11f64, &mut w);
+        c.push(all_significant, &mut w);
+
+        let mut r = DoubleStreamLeadTrailIterator::new(StringReader::new(w.string));
+        assert_eq!(r.next(), Some(11f64));
+        assert_eq!(r.next(), Some(all_significant));
+        assert_eq!(r.next(), None);
+    }
+
+    #[test]
+    fn fuzzer_vec () {
+        // throw some random values at it and see if they decode correctly
+        let mut w = VecWriter::new();
+        let mut c = DoubleStreamLeadTrail::new();
+        let mut numbers = Vec::new();
+
+        for i in 0..1_000 {
+            let i = (i as f64).sqrt();
+            c.push(i, &mut w);
+            numbers.push(i);
+        }
+
+        let r = DoubleStreamLeadTrailIterator::new(VecReader::new(&w.bit_vector, w.used_bits_last_elm));
+
+        let read: Vec<f64> = r.collect();
+        assert_eq!(read, numbers);
+    }
+
+    #[test]
+    fn invalid_window() {
+        // no window has been defined after the first value
+        let mut r = StringReader::new(format!("{:064b}101", 0));
+        let mut p = DoubleStreamLeadTrailParser::new();
+        assert_eq!(p.next(&mut r), Ok(Some(0f64)));
+        assert_eq!(p.next(&mut r), Err(DecodeError::InvalidWindow { offset: 64, leading_zeros: 64, meaningful_bits: 0 }));
+
+        // 31 leading zeros and 34 meaningful bits
+        let mut r = StringReader::new(format!("{:064b}1111111100001", 0));
+        let mut p = DoubleStreamLeadTrailParser::new();
+        assert_eq!(p.next(&mut r), Ok(Some(0f64)));
+        assert_eq!(p.next(&mut r), Err(DecodeError::InvalidWindow { offset: 64, leading_zeros: 31, meaningful_bits: 34 }));
+    }
+}
@@ -0,0 +1,332 @@
+//! A disassembler for `TimeAndValueStream` buffers, for seeing what the
+//! encoder emitted when compression ratios look wrong. Each sample is
+//! described by a `DumpRecord`, holding its bit offset, the control codes of
+//! its timestamp and value, and how many bits each took.
+//!
+//! Samples are decoded by the same parsers as `TimeAndValueIterator`, with
+//! the control codes taken from the bits they read, so a dump always agrees
+//! with what decoding sees. Only the default `DoubleStreamWriter` codec is
+//! supported.
+
+use std::fmt;
+
+use double_stream::{DoubleStreamParser, DoubleStreamState};
+use error::DecodeError;
+use stream::Reader;
+use timestamp_stream::{TimestampFormat, TimestampStreamParser, TimestampStreamState};
+
+/// How a timestamp was encoded.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TimestampControl {
+    /// The first timestamp, stored as its delta from the header time.
+    First,
+    /// `0`, the delta of deltas is zero.
+    Zero,
+    /// `10`, `110` or `1110`, the delta of deltas is in the bucket with this
+    /// index.
+    Bucket(u8),
+    /// `1111`, the delta of deltas didn't fit a bucket.
+    Escape,
+}
+
+impl fmt::Display for TimestampControl {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            TimestampControl::First => write!(f, "first"),
+            TimestampControl::Zero => write!(f, "0"),
+            TimestampControl::Bucket(bucket) => write!(f, "{}0", "1".repeat(bucket as usize + 1)),
+            TimestampControl::Escape => write!(f, "1111"),
+        }
+    }
+}
+
+/// How a value was encoded.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ValueControl {
+    /// The first value, stored in full.
+    First,
+    /// `0`, the same value as before.
+    Repeat,
+    /// `10`, the meaningful bits of the XOR in the previous window.
+    ReuseWindow,
+    /// `11`, a new window followed by the meaningful bits of the XOR.
+    NewWindow,
+}
+
+impl fmt::Display for ValueControl {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ValueControl::First => write!(f, "first"),
+            ValueControl::Repeat => write!(f, "0"),
+            ValueControl::ReuseWindow => write!(f, "10"),
+            ValueControl::NewWindow => write!(f, "11"),
+        }
+    }
+}
+
+/// One decoded sample, with how its timestamp and value were encoded.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct DumpRecord {
+    /// Where the sample starts in the stream.
+    pub bit_offset: usize,
+    pub timestamp: u64,
+    pub timestamp_control: TimestampControl,
+    /// The delta to the previous timestamp, or to the header time for the
+    /// first one.
+    pub delta: i64,
+    /// Zero for the first timestamp.
+    pub delta_of_deltas: i64,
+    pub timestamp_bits: usize,
+    pub value: f64,
+    pub value_control: ValueControl,
+    /// The window of the XOR with the previous value, for `ReuseWindow` and
+    /// `NewWindow`. A new window stores at most 31 leading zeros, see
+    /// `[LEADING31]`.
+    pub leading_zeros: Option<u8>,
+    pub meaningful_bits: Option<u8>,
+    pub value_bits: usize,
+}
+
+impl DumpRecord {
+    /// The total number of bits of the sample.
+    pub fn bits(&self) -> usize {
+        self.timestamp_bits + self.value_bits
+    }
+}
+
+impl fmt::Display for DumpRecord {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:>8}  {:>20} {:>5} dod {:>6} ({:>2} bits)  {:>5}",
+               self.bit_offset, self.timestamp, self.timestamp_control.to_string(),
+               self.delta_of_deltas, self.timestamp_bits, self.value_control.to_string())?;
+        match (self.leading_zeros, self.meaningful_bits) {
+            (Some(leading_zeros), Some(meaningful_bits)) => write!(f, " lz {:>2} m {:>2}", leading_zeros, meaningful_bits)?,
+            _ => write!(f, "          ")?,
+        }
+        write!(f, " ({:>2} bits)  {}", self.value_bits, self.value)
+    }
+}
+
+/// Passes reads through to `inner`, remembering the bits read.
+struct Recorder<'r, R: ?Sized + 'r> {
+    inner: &'r mut R,
+    reads: Vec<(u8, u64)>,
+}
+
+impl<'r, R> Recorder<'r, R> where R: Reader + ?Sized {
+    fn bits(&self) -> usize {
+        self.reads.iter().map(|&(count, _)| count as usize).sum()
+    }
+}
+
+impl<'r, R> Reader for Recorder<'r, R> where R: Reader + ?Sized {
+    fn read(&mut self, count: u8) -> Option<u64> {
+        let bits = self.inner.read(count)?;
+        self.reads.push((count, bits));
+        Some(bits)
+    }
+
+    fn position(&self) -> usize {
+        self.inner.position()
+    }
+}
+
+/// Iterates over the samples of a stream as `DumpRecord`s. Iteration stops at
+/// the first decode error, which is then available from `error`.
+pub struct DumpIterator<R> where R: Reader {
+    timestamp_parser: TimestampStreamParser,
+    value_parser: DoubleStreamParser,
+    reader: R,
+    error: Option<DecodeError>,
+}
+
+impl<R> DumpIterator<R> where R: Reader {
+    pub fn new(reader: R, header_time: u64) -> Self {
+        DumpIterator::with_format(reader, header_time, TimestampFormat::default())
+    }
+
+    pub fn with_format(reader: R, header_time: u64, format: TimestampFormat) -> Self {
+        DumpIterator {
+            timestamp_parser: TimestampStreamParser::with_format(header_time, format),
+            value_parser: DoubleStreamParser::new(),
+            reader,
+            error: None,
+        }
+    }
+
+    /// Returns `Ok(None)` at the end of the stream, or an error if the stream
+    /// is truncated or malformed.
+    pub fn try_next(&mut self) -> Result<Option<DumpRecord>, DecodeError> {
+        let bit_offset = self.reader.position();
+
+        let previous = self.timestamp_parser.state();
+        let mut recorder = Recorder { inner: &mut self.reader, reads: Vec::new() };
+        let timestamp = match self.timestamp_parser.next(&mut recorder)? {
+            Some(timestamp) => timestamp,
+            None => return Ok(None),
+        };
+        let delta = match self.timestamp_parser.state() {
+            TimestampStreamState::Following { delta, .. } => delta,
+            TimestampStreamState::Initial { .. } => unreachable!(),
+        };
+        let (timestamp_control, delta_of_deltas) = match previous {
+            TimestampStreamState::Initial { .. } => (TimestampControl::First, 0),
+            TimestampStreamState::Following { delta: previous_delta, .. } =>
+                (timestamp_control(&recorder.reads), delta.wrapping_sub(previous_delta)),
+        };
+        let timestamp_bits = recorder.bits();
+
+        let previous = self.value_parser.state();
+        recorder.reads.clear();
+        let offset = recorder.position();
+        let value = match self.value_parser.next(&mut recorder)? {
+            Some(value) => value,
+            None => return Err(DecodeError::TruncatedPayload { offset }),
+        };
+        let (value_control, window) = value_control(previous, &recorder.reads);
+
+        Ok(Some(DumpRecord {
+            bit_offset,
+            timestamp,
+            timestamp_control,
+            delta,
+            delta_of_deltas,
+            timestamp_bits,
+            value,
+            value_control,
+            leading_zeros: window.map(|(leading_zeros, _)| leading_zeros),
+            meaningful_bits: window.map(|(_, meaningful_bits)| meaningful_bits),
+            value_bits: recorder.bits(),
+        }))
+    }
+
+    pub fn error(&self) -> Option<&DecodeError> {
+        self.error.as_ref()
+    }
+}
+
+impl<R> Iterator for DumpIterator<R> where R: Reader {
+    type Item = DumpRecord;
+
+    fn next(&mut self) -> Option<DumpRecord> {
+        if self.error.is_some() {
+            return None;
+        }
+
+        self.try_next().unwrap_or_else(|error| {
+            self.error = Some(error);
+            None
+        })
+    }
+}
+
+/// The control code of a timestamp after the first, from the bits read for
+/// it: a zero, or a one followed by up to three more ones ended by a zero.
+fn timestamp_control(reads: &[(u8, u64)]) -> TimestampControl {
+    if reads[0].1 == 0 {
+        return TimestampControl::Zero;
+    }
+
+    let ones = reads[1..].iter().take(3).take_while(|&&(_, bits)| bits == 1).count();
+    match ones {
+        3 => TimestampControl::Escape,
+        _ => TimestampControl::Bucket(ones as u8),
+    }
+}
+
+/// The control code and window of a value, from the state before it and the
+/// bits read for it.
+fn value_control(previous: DoubleStreamState, reads: &[(u8, u64)]) -> (ValueControl, Option<(u8, u8)>) {
+    let previous_xor = match previous {
+        DoubleStreamState::Initial => return (ValueControl::First, None),
+        DoubleStreamState::Following { xor, .. } => xor,
+    };
+
+    match (reads[0].1, reads.get(1).map(|&(_, bits)| bits)) {
+        (0, _) => (ValueControl::Repeat, None),
+        (_, Some(0)) => {
+            let leading_zeros = previous_xor.leading_zeros();
+            let meaningful_bits = 64 - leading_zeros - previous_xor.trailing_zeros();
+            (ValueControl::ReuseWindow, Some((leading_zeros as u8, meaningful_bits as u8)))
+        },
+        _ => (ValueControl::NewWindow, Some((reads[2].1 as u8, reads[3].1 as u8 + 1))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use time_and_value_stream::TimeAndValueStream;
+    use vec_stream::{VecWriter, VecReader};
+
+    #[test]
+    fn controls() {
+        let header_time = 1000;
+        let samples = [(1010, 0.0), (1020, 1.0), (1035, 1.0), (1045, 3.0), (2000, 2.0), (2010, 2.5), (100_000, 2.5)];
+        let mut w = VecWriter::new();
+        let mut c = TimeAndValueStream::new(header_time);
+        for &(timestamp, value) in samples.iter() {
+            c.push(timestamp, value, &mut w);
+        }
+
+        let mut r = DumpIterator::new(VecReader::new(&w.bit_vector, w.used_bits_last_elm), header_time);
+        let records: Vec<DumpRecord> = r.by_ref().collect();
+        assert_eq!(r.error(), None);
+
+        let controls: Vec<String> = records.iter().map(|record| format!("{} {} {} {:?} {:?}",
+            record.timestamp_control, record.delta_of_deltas, record.value_control, record.leading_zeros, record.meaningful_bits)).collect();
+        assert_eq!(controls, vec![
+            "first 0 first None None",
+            // 1.0 ^ 0.0 = 0x3FF0000000000000
+            "0 0 11 Some(2) Some(10)",
+            "10 5 0 None None",
+            // 3.0 ^ 1.0 = 0x7FF8000000000000
+            "10 -5 11 Some(1) Some(12)",
+            // 2.0 ^ 3.0 = 0x0008000000000000, which fits in the previous window
+            "1110 945 10 Some(1) Some(12)",
+            // 2.5 ^ 2.0 = 0x0004000000000000
+            "1110 -945 11 Some(13) Some(1)",
+            "1111 97980 0 None None",
+        ]);
+
+        assert_eq!(records[0].timestamp_bits, 14);
+        assert_eq!(records[0].value_bits, 64);
+        assert_eq!(records[1].bits(), 1 + 2 + 5 + 6 + 10);
+        assert_eq!(records[6].timestamp_bits, 4 + 32);
+        for pair in records.windows(2) {
+            assert_eq!(pair[0].bit_offset + pair[0].bits(), pair[1].bit_offset);
+        }
+        assert_eq!(records[6].bit_offset + records[6].bits(), w.len());
+        assert_eq!(records.iter().map(|record| (record.timestamp, record.value)).collect::<Vec<_>>(), samples.to_vec());
+    }
+
+    #[test]
+    fn truncated() {
+        let mut w = VecWriter::new();
+        let mut c = TimeAndValueStream::new(0);
+        c.push(10, 1.0, &mut w);
+        c.push(20, 2.0, &mut w);
+
+        let mut r = DumpIterator::new(VecReader::new(&w.bit_vector, w.used_bits_last_elm - 1), 0);
+        assert_eq!(r.by_ref().count(), 1);
+        assert!(r.error().is_some());
+    }
+
+    #[test]
+    fn display() {
+        let record = DumpRecord {
+            bit_offset: 78,
+            timestamp: 1020,
+            timestamp_control: TimestampControl::Bucket(1),
+            delta: 10,
+            delta_of_deltas: -3,
+            timestamp_bits: 12,
+            value: 1.5,
+            value_control: ValueControl::NewWindow,
+            leading_zeros: Some(2),
+            meaningful_bits: Some(10),
+            value_bits: 23,
+        };
+        assert_eq!(record.to_string(), "      78                  1020   110 dod     -3 (12 bits)     11 lz  2 m 10 (23 bits)  1.5");
+    }
+}
@@ -0,0 +1,147 @@
+//! Errors returned when a stream or block can't be encoded or decoded.
+
+use std::error::Error;
+use std::fmt;
+use std::io;
+
+use timestamp_stream::TimestampFormat;
+
+pub use stream::Reader;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum DecodeError {
+    /// The stream ended in the middle of a control code.
+    TruncatedControl { offset: usize },
+    /// The stream ended after a control code, but before its payload.
+    TruncatedPayload { offset: usize },
+    /// A window of leading zeros and meaningful bits that doesn't fit in a
+    /// 64 bit value, or a reused window that is empty.
+    InvalidWindow { offset: usize, leading_zeros: u8, meaningful_bits: u8 },
+    /// A varint longer than 64 bits.
+    InvalidVarint { offset: usize },
+}
+
+impl DecodeError {
+    /// The bit offset into the stream where the failing read started.
+    pub fn offset(&self) -> usize {
+        match *self {
+            DecodeError::TruncatedControl { offset } => offset,
+            DecodeError::TruncatedPayload { offset } => offset,
+            DecodeError::InvalidWindow { offset, .. } => offset,
+            DecodeError::InvalidVarint { offset } => offset,
+        }
+    }
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            DecodeError::TruncatedControl { offset } =>
+                write!(f, "truncated control code at bit {}", offset),
+            DecodeError::TruncatedPayload { offset } =>
+                write!(f, "truncated payload at bit {}", offset),
+            DecodeError::InvalidWindow { offset, leading_zeros, meaningful_bits } =>
+                write!(f, "invalid window at bit {}: {} leading zeros and {} meaningful bits",
+                       offset, leading_zeros, meaningful_bits),
+            DecodeError::InvalidVarint { offset } =>
+                write!(f, "invalid varint at bit {}", offset),
+        }
+    }
+}
+
+impl Error for DecodeError {}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum EncodeError {
+    /// The first timestamp of a stream is before its header time.
+    BeforeHeader { timestamp: u64, header_time: u64 },
+    /// The first timestamp of a stream is too far after its header time.
+    FirstDeltaTooLarge { delta: u64, max: u64 },
+    /// A timestamp is earlier than the previous one.
+    OutOfOrder { timestamp: u64, previous: u64 },
+    /// A timestamp is more than `i64::MAX` after the previous one.
+    DeltaTooLarge { delta: u64 },
+    /// The delta of deltas doesn't fit in the largest bucket.
+    DeltaOfDeltasTooLarge { delta_of_deltas: i64 },
+}
+
+impl fmt::Display for EncodeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            EncodeError::BeforeHeader { timestamp, header_time } =>
+                write!(f, "timestamp {} is before header time {}", timestamp, header_time),
+            EncodeError::FirstDeltaTooLarge { delta, max } =>
+                write!(f, "first delta {} is larger than {}", delta, max),
+            EncodeError::OutOfOrder { timestamp, previous } =>
+                write!(f, "timestamp {} is before previous timestamp {}", timestamp, previous),
+            EncodeError::DeltaTooLarge { delta } =>
+                write!(f, "delta {} is out of range", delta),
+            EncodeError::DeltaOfDeltasTooLarge { delta_of_deltas } =>
+                write!(f, "delta of deltas {} is out of range", delta_of_deltas),
+        }
+    }
+}
+
+impl Error for EncodeError {}
+
+#[derive(Debug)]
+pub enum BlockError {
+    Io(io::Error),
+    /// The file doesn't start with the magic bytes of a block file.
+    BadMagic,
+    UnsupportedVersion(u8),
+    UnknownCodec(u8),
+    UnknownTimestampVersion(u8),
+    UnknownPrecision(u8),
+    /// The delta of delta bucket widths aren't a valid `DodTable`.
+    InvalidDodTable([u8; 3]),
+    /// The block was written with another timestamp format than expected.
+    FormatMismatch { expected: TimestampFormat, actual: TimestampFormat },
+    /// The CRC32 of the payload doesn't match the one in the header.
+    ChecksumMismatch { expected: u32, actual: u32 },
+}
+
+impl fmt::Display for BlockError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            BlockError::Io(ref error) => write!(f, "{}", error),
+            BlockError::BadMagic => write!(f, "not a block file"),
+            BlockError::UnsupportedVersion(version) => write!(f, "unsupported block file version {}", version),
+            BlockError::UnknownCodec(codec) => write!(f, "unknown value codec {}", codec),
+            BlockError::UnknownTimestampVersion(version) => write!(f, "unknown timestamp version {}", version),
+            BlockError::UnknownPrecision(precision) => write!(f, "unknown timestamp precision {}", precision),
+            BlockError::InvalidDodTable(bucket_bits) => write!(f, "invalid delta of delta buckets {:?}", bucket_bits),
+            BlockError::FormatMismatch { expected, actual } =>
+                write!(f, "timestamp format mismatch: expected {:?}, got {:?}", expected, actual),
+            BlockError::ChecksumMismatch { expected, actual } =>
+                write!(f, "checksum mismatch: expected {:08x}, got {:08x}", expected, actual),
+        }
+    }
+}
+
+impl Error for BlockError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match *self {
+            BlockError::Io(ref error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for BlockError {
+    fn from(error: io::Error) -> Self {
+        BlockError::Io(error)
+    }
+}
+
+/// Read `count` bits belonging to a control code.
+pub(crate) fn read_control<R: Reader + ?Sized>(reader: &mut R, count: u8) -> Result<u64, DecodeError> {
+    let offset = reader.position();
+    reader.read(count).ok_or(DecodeError::TruncatedControl { offset })
+}
+
+/// Read `count` bits of payload following a control code.
+pub(crate) fn read_payload<R: Reader + ?Sized>(reader: &mut R, count: u8) -> Result<u64, DecodeError> {
+    let offset = reader.position();
+    reader.read(count).ok_or(DecodeError::TruncatedPayload { offset })
+}
@@ -0,0 +1,284 @@
+//! This module provides adapters between the bit `Writer` and `Reader` traits
+//! and `std::io`, so streams can be written straight to files, sockets or
+//! compressors, and read back from them. Bits are written most significant bit
+//! first, the same layout as `VecWriter::to_bytes`.
+//!
+//! The bit traits can't fail, so I/O errors are kept and reported later:
+//! `IoBitWriter::finish` returns the first write error and
+//! `IoBitReader::error` the first read error.
+//!
+//! The last byte is padded with zeros. A zero bit is a valid sample in most
+//! streams, so the reader must be told the number of bits written, from
+//! `IoBitWriter::len`, for the padding not to be decoded.
+
+use std::io::{self, Read, Write};
+
+pub use stream::{Writer, Reader};
+
+const BUFFER_SIZE: usize = 8 * 1024;
+
+pub struct IoBitWriter<W: Write> {
+    inner: W,
+    buffer: Vec<u8>,
+    pending: u128,
+    pending_bits: u8,
+    len: u64,
+    error: Option<io::Error>,
+}
+
+impl<W> IoBitWriter<W> where W: Write {
+    pub fn new(inner: W) -> Self {
+        IoBitWriter {
+            inner,
+            buffer: Vec::with_capacity(BUFFER_SIZE),
+            pending: 0,
+            pending_bits: 0,
+            len: 0,
+            error: None,
+        }
+    }
+
+    /// The number of bits written so far.
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn get_ref(&self) -> &W {
+        &self.inner
+    }
+
+    /// Writes the remaining bits, padding the last byte with zeros, flushes
+    /// the inner writer and returns it. Bits not yet written are lost if the
+    /// writer is dropped without calling this.
+    pub fn finish(mut self) -> io::Result<W> {
+        if self.pending_bits > 0 {
+            let byte = (self.pending << (8 - self.pending_bits)) as u8;
+            self.buffer.push(byte);
+            self.pending_bits = 0;
+        }
+        self.flush_buffer();
+
+        if let Some(error) = self.error.take() {
+            return Err(error);
+        }
+        self.inner.flush()?;
+        Ok(self.inner)
+    }
+
+    fn flush_buffer(&mut self) {
+        if self.error.is_none() {
+            if let Err(error) = self.inner.write_all(&self.buffer) {
+                self.error = Some(error);
+            }
+        }
+        self.buffer.clear();
+    }
+}
+
+impl<W> Writer for IoBitWriter<W> where W: Write {
+    fn write(&mut self, bits: u64, count: u8) {
+        self.pending = (self.pending << count) | bits as u128;
+        self.pending_bits += count;
+        self.len += count as u64;
+
+        while self.pending_bits >= 8 {
+            self.pending_bits -= 8;
+            self.buffer.push((self.pending >> self.pending_bits) as u8);
+        }
+
+        if self.buffer.len() >= BUFFER_SIZE {
+            self.flush_buffer();
+        }
+    }
+
+    fn position(&self) -> usize {
+        self.len as usize
+    }
+}
+
+pub struct IoBitReader<R: Read> {
+    inner: R,
+    buffer: Vec<u8>,
+    buffer_position: usize,
+    buffer_len: usize,
+    pending: u128,
+    pending_bits: u8,
+    position: u64,
+    num_bits: u64,
+    error: Option<io::Error>,
+}
+
+impl<R> IoBitReader<R> where R: Read {
+    /// Reads the first `num_bits` bits of `inner`, usually the `len` of the
+    /// `IoBitWriter` that wrote them.
+    pub fn new(inner: R, num_bits: u64) -> Self {
+        IoBitReader {
+            inner,
+            buffer: vec![0; BUFFER_SIZE],
+            buffer_position: 0,
+            buffer_len: 0,
+            pending: 0,
+            pending_bits: 0,
+            position: 0,
+            num_bits,
+            error: None,
+        }
+    }
+
+    /// The first error from the inner reader. Reads return `None` once an
+    /// error has occurred.
+    pub fn error(&self) -> Option<&io::Error> {
+        self.error.as_ref()
+    }
+
+    fn next_byte(&mut self) -> Option<u8> {
+        if self.buffer_position == self.buffer_len {
+            if self.error.is_some() {
+                return None;
+            }
+            loop {
+                match self.inner.read(&mut self.buffer) {
+                    Ok(0) => return None,
+                    Ok(len) => {
+                        self.buffer_position = 0;
+                        self.buffer_len = len;
+                        break;
+                    },
+                    Err(ref error) if error.kind() == io::ErrorKind::Interrupted => {},
+                    Err(error) => {
+                        self.error = Some(error);
+                        return None;
+                    },
+                }
+            }
+        }
+
+        self.buffer_position += 1;
+        Some(self.buffer[self.buffer_position - 1])
+    }
+}
+
+impl<R> Reader for IoBitReader<R> where R: Read {
+    fn read(&mut self, count: u8) -> Option<u64> {
+        if self.num_bits - self.position < count as u64 {
+            return None;
+        }
+
+        while self.pending_bits < count {
+            let byte = self.next_byte()?;
+            self.pending = (self.pending << 8) | byte as u128;
+            self.pending_bits += 8;
+        }
+
+        self.pending_bits -= count;
+        self.position += count as u64;
+        let bits = (self.pending >> self.pending_bits) as u64;
+        if count == 64 {
+            Some(bits)
+        } else {
+            Some(bits & ((1 << count) - 1))
+        }
+    }
+
+    fn position(&self) -> usize {
+        self.position as usize
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use vec_stream::VecWriter;
+    use time_and_value_stream::*;
+
+    /// Hands out at most three bytes per read.
+    struct Trickle<'a>(&'a [u8]);
+
+    impl<'a> Read for Trickle<'a> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let len = buf.len().min(self.0.len()).min(3);
+            buf[..len].copy_from_slice(&self.0[..len]);
+            self.0 = &self.0[len..];
+            Ok(len)
+        }
+    }
+
+    #[derive(Debug)]
+    struct Failing;
+
+    impl Write for Failing {
+        fn write(&mut self, _: &[u8]) -> io::Result<usize> {
+            Err(io::Error::new(io::ErrorKind::Other, "disk full"))
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn same_bytes_as_vec_writer() {
+        let mut v = VecWriter::new();
+        let mut w = IoBitWriter::new(Vec::new());
+        for i in 0..1_000u64 {
+            let count = (i % 64 + 1) as u8;
+            let bits = i.wrapping_mul(0x9E3779B97F4A7C15) >> (64 - count);
+            v.write(bits, count);
+            w.write(bits, count);
+        }
+
+        assert_eq!(w.len(), v.len() as u64);
+        assert_eq!(w.finish().unwrap(), v.to_bytes());
+    }
+
+    #[test]
+    fn read_bits() {
+        let data = [0b11010000, 0x01, 0x23, 0x45, 0x67, 0x89, 0xAB, 0xCD, 0xEF, 0b10000000];
+        let mut r = IoBitReader::new(Trickle(&data), 73);
+        assert_eq!(r.read(4), Some(0b1101));
+        assert_eq!(r.read(4), Some(0));
+        assert_eq!(r.read(64), Some(0x0123456789ABCDEF));
+        assert_eq!(r.position(), 72);
+        assert_eq!(r.read(2), None);
+        assert_eq!(r.read(1), Some(1));
+        assert_eq!(r.read(1), None);
+        assert!(r.error().is_none());
+    }
+
+    #[test]
+    fn time_and_value() {
+        let header_time = 10000;
+        let numbers = [(10005, 0.34f64), (10065, 0.35f64), (10124, 0.72f64), (10247, 0.42f64), (10365, 1.12f64)];
+
+        let mut w = IoBitWriter::new(Vec::new());
+        let mut c = TimeAndValueStream::new(header_time);
+        for &(timestamp, value) in numbers.iter() {
+            c.push(timestamp, value, &mut w);
+        }
+        let len = w.len();
+        let bytes = w.finish().unwrap();
+
+        let r = TimeAndValueIterator::new(IoBitReader::new(Trickle(&bytes), len), header_time);
+        assert_eq!(r.collect::<Vec<_>>(), numbers.to_vec());
+    }
+
+    #[test]
+    fn write_error() {
+        let mut w = IoBitWriter::new(Failing);
+        w.write(0b101, 3);
+        assert_eq!(w.finish().unwrap_err().to_string(), "disk full");
+    }
+
+    #[test]
+    fn truncated_input() {
+        let data = [0xFF, 0xFF];
+        let mut r = IoBitReader::new(&data[..], 32);
+        assert_eq!(r.read(12), Some(0xFFF));
+        assert_eq!(r.read(8), None);
+        assert!(r.error().is_none());
+    }
+}
@@ -1,13 +1,23 @@
 #[cfg(test)]
 mod bit_string_stream;
+#[cfg(test)]
+mod properties;
 
 pub mod vec_stream;
+pub mod slice_stream;
+pub mod io_stream;
 pub mod stream;
 pub use stream::*;
 
+pub mod error;
+pub use error::*;
+
 pub mod timestamp_stream;
 pub use timestamp_stream::*;
 
+pub mod dod_table;
+pub use dod_table::DodTable;
+
 pub mod double_stream;
 pub use double_stream::*;
 
@@ -17,3 +27,30 @@ pub use double_stream_lead_trail::*;
 pub mod time_and_value_stream;
 pub use time_and_value_stream::*;
 
+pub mod restart_index;
+pub use restart_index::*;
+
+pub mod reorder;
+pub use reorder::*;
+
+pub mod tsz;
+
+pub mod prometheus_chunk;
+
+pub mod series;
+pub use series::*;
+
+pub mod rollup;
+pub use rollup::*;
+
+pub mod ts_map;
+pub use ts_map::*;
+
+pub mod retention;
+pub use retention::*;
+
+pub mod shard_log;
+
+pub mod block_file;
+
+pub mod dump;
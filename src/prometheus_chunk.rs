@@ -0,0 +1,376 @@
+//! Reading and writing of Prometheus TSDB XOR chunks, the encoding Prometheus
+//! uses for float samples. A chunk is laid out as:
+//!
+//! - the number of samples, as a big-endian `u16`
+//! - the first timestamp as a signed varint, and the first value in 64 bits
+//! - the delta to the second timestamp as an unsigned varint, and the second
+//!   value
+//! - for every following sample, the delta of deltas and the value
+//!
+//! Timestamps are `i64` milliseconds, so the delta of delta buckets are wider
+//! than in `TimestampStreamWriter`: 14, 17 and 20 bits, or else 64. Values are
+//! compressed as by `TszValueWriter`.
+
+use vec_stream::VecWriter;
+use slice_stream::SliceReader;
+use stream::{Writer, Reader};
+use tsz::{TszValueWriter, TszValueParser, fits_bucket};
+use error::{DecodeError, read_control, read_payload};
+
+/// The most samples a chunk can hold, as the count is stored in 16 bits.
+/// Prometheus itself cuts chunks at 120 samples.
+pub const MAX_SAMPLES: usize = u16::MAX as usize;
+
+const BUCKET_BITS: [u8; 3] = [14, 17, 20];
+
+pub struct XorChunkWriter {
+    data: VecWriter,
+    count: u16,
+    timestamp: i64,
+    delta: u64,
+    values: TszValueWriter,
+}
+
+impl XorChunkWriter {
+    pub fn new() -> Self {
+        let mut data = VecWriter::new();
+        // the sample count, filled in by `bytes`
+        data.write(0, 16);
+
+        XorChunkWriter {
+            data,
+            count: 0,
+            timestamp: 0,
+            delta: 0,
+            values: TszValueWriter::new(),
+        }
+    }
+
+    /// The number of samples in the chunk.
+    pub fn len(&self) -> usize {
+        self.count as usize
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.count == 0
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.len() == MAX_SAMPLES
+    }
+
+    /// Panics if the chunk is full. Timestamps are expected to be in order,
+    /// as Prometheus rejects out of order samples before they reach a chunk.
+    pub fn append(&mut self, timestamp: i64, value: f64) {
+        assert!(!self.is_full());
+
+        let delta = match self.count {
+            0 => {
+                write_varint(&mut self.data, zigzag(timestamp));
+                0
+            },
+            1 => {
+                let delta = timestamp.wrapping_sub(self.timestamp) as u64;
+                write_varint(&mut self.data, delta);
+                delta
+            },
+            _ => {
+                let delta = timestamp.wrapping_sub(self.timestamp) as u64;
+                let delta_of_deltas = delta.wrapping_sub(self.delta) as i64;
+
+                if delta_of_deltas == 0 {
+                    self.data.write(0, 1);
+                } else if let Some(bucket) = BUCKET_BITS.iter().position(|&bits| fits_bucket(delta_of_deltas, bits)) {
+                    // 10, 110 or 1110
+                    let bits = BUCKET_BITS[bucket];
+                    self.data.write((1 << (bucket + 2)) - 2, bucket as u8 + 2);
+                    self.data.write(delta_of_deltas as u64 & ((1 << bits) - 1), bits);
+                } else {
+                    self.data.write(0b1111, 4);
+                    self.data.write(delta_of_deltas as u64, 64);
+                }
+                delta
+            },
+        };
+
+        self.values.push(value, &mut self.data);
+        self.timestamp = timestamp;
+        self.delta = delta;
+        self.count += 1;
+    }
+
+    /// The chunk, as stored by Prometheus.
+    pub fn bytes(&self) -> Vec<u8> {
+        let mut bytes = self.data.to_bytes();
+        bytes[0..2].copy_from_slice(&self.count.to_be_bytes());
+        bytes
+    }
+}
+
+impl Default for XorChunkWriter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Iterates over the samples of a chunk. Iteration stops after the number of
+/// samples in the header, or at the first decode error, which is then
+/// available from `error`.
+pub struct XorChunkIterator<'a> {
+    reader: SliceReader<'a>,
+    samples: usize,
+    read: usize,
+    timestamp: i64,
+    delta: u64,
+    values: TszValueParser,
+    error: Option<DecodeError>,
+}
+
+impl<'a> XorChunkIterator<'a> {
+    pub fn new(chunk: &'a [u8]) -> Self {
+        let mut reader = SliceReader::new(chunk, chunk.len() * 8);
+        let (samples, error) = match reader.read(16) {
+            Some(samples) => (samples as usize, None),
+            None => (0, Some(DecodeError::TruncatedPayload { offset: 0 })),
+        };
+
+        XorChunkIterator {
+            reader,
+            samples,
+            read: 0,
+            timestamp: 0,
+            delta: 0,
+            values: TszValueParser::new(),
+            error,
+        }
+    }
+
+    /// The number of samples in the chunk, from its header.
+    pub fn samples(&self) -> usize {
+        self.samples
+    }
+
+    /// Returns `Ok(None)` once all samples have been read, or an error if the
+    /// chunk is truncated or malformed.
+    pub fn try_next(&mut self) -> Result<Option<(i64, f64)>, DecodeError> {
+        if self.read == self.samples {
+            return Ok(None);
+        }
+
+        let reader = &mut self.reader;
+        let offset = reader.position();
+        let (timestamp, delta) = match self.read {
+            0 => {
+                let timestamp = unzigzag(read_varint(reader)?);
+                let value = read_payload(reader, 64)?;
+                self.values.set_first(value);
+                self.timestamp = timestamp;
+                self.read += 1;
+                return Ok(Some((timestamp, f64::from_bits(value))));
+            },
+            1 => {
+                let delta = read_varint(reader)?;
+                (self.timestamp.wrapping_add(delta as i64), delta)
+            },
+            _ => {
+                // count the ones before the first zero: 0, 10, 110, 1110 or 1111
+                let mut bucket = 0;
+                while bucket < 4 && read_control(reader, 1)? == 1 {
+                    bucket += 1;
+                }
+
+                let delta_of_deltas = match bucket {
+                    0 => 0,
+                    4 => read_payload(reader, 64)? as i64,
+                    _ => {
+                        let bits = BUCKET_BITS[bucket - 1];
+                        let payload = read_payload(reader, bits)? as i64;
+                        if payload > 1 << (bits - 1) { payload - (1 << bits) } else { payload }
+                    },
+                };
+
+                let delta = self.delta.wrapping_add(delta_of_deltas as u64);
+                (self.timestamp.wrapping_add(delta as i64), delta)
+            },
+        };
+
+        let value = match self.values.next(reader)? {
+            Some(value) => value,
+            None => return Err(DecodeError::TruncatedPayload { offset }),
+        };
+
+        self.timestamp = timestamp;
+        self.delta = delta;
+        self.read += 1;
+        Ok(Some((timestamp, value)))
+    }
+
+    pub fn error(&self) -> Option<&DecodeError> {
+        self.error.as_ref()
+    }
+}
+
+impl<'a> Iterator for XorChunkIterator<'a> {
+    type Item = (i64, f64);
+
+    fn next(&mut self) -> Option<(i64, f64)> {
+        if self.error.is_some() {
+            return None;
+        }
+
+        self.try_next().unwrap_or_else(|error| {
+            self.error = Some(error);
+            None
+        })
+    }
+}
+
+fn zigzag(number: i64) -> u64 {
+    ((number << 1) ^ (number >> 63)) as u64
+}
+
+fn unzigzag(number: u64) -> i64 {
+    (number >> 1) as i64 ^ -((number & 1) as i64)
+}
+
+/// Writes a LEB128 varint, a byte at a time like Prometheus, so it needn't be
+/// byte aligned.
+fn write_varint(writer: &mut dyn Writer, mut number: u64) {
+    while number >= 0x80 {
+        writer.write((number & 0x7F) | 0x80, 8);
+        number >>= 7;
+    }
+    writer.write(number, 8);
+}
+
+fn read_varint(reader: &mut dyn Reader) -> Result<u64, DecodeError> {
+    let offset = reader.position();
+    let mut number = 0;
+    for i in 0..10 {
+        let byte = read_payload(reader, 8)?;
+        if i == 9 && byte > 1 {
+            break;
+        }
+        number |= (byte & 0x7F) << (7 * i);
+        if byte < 0x80 {
+            return Ok(number);
+        }
+    }
+    Err(DecodeError::InvalidVarint { offset })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const T: i64 = 1600000000000;
+
+    fn samples() -> Vec<(i64, f64)> {
+        vec![
+            (T, 0.5),
+            (T + 15000, 0.5),
+            (T + 30000, 0.75),
+            (T + 45001, 12.0),
+            (T + 60000, 12.0),
+            (T + 3660000, -3.25),
+            (T + 3675000, 1e-9),
+            (T + 3698192, 1e-9),
+            (T + 3779728, 7.0),
+        ]
+    }
+
+    // The chunk `chunkenc.NewXORChunk` writes for `samples` in Prometheus
+    // v0.47.2, the version in testdata/golden/go.mod, as printed by
+    //
+    //     cd testdata/golden
+    //     go get github.com/prometheus/prometheus@v0.47.2
+    //     go run ./prometheus
+    //
+    // These bytes were worked out from the source of that version and have
+    // not been regenerated with the command yet. Paste its output over them,
+    // and commit the go.sum it leaves behind.
+    const GOLDEN: [u8; 70] = [
+        0x00, 0x09, 0x80, 0x80, 0xf4, 0xf6, 0x90, 0x5d, 0x3f, 0xe0, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x98, 0x75, 0x36, 0x03, 0x80, 0x01, 0xc2, 0x4f, 0xfe, 0xff, 0xf9, 0xe0, 0x00, 0x00, 0x00, 0x00,
+        0x06, 0xd6, 0x7d, 0x38, 0x0f, 0x80, 0x23, 0xff, 0xff, 0xff, 0xff, 0xff, 0xf9, 0x29, 0x83, 0x18,
+        0x00, 0xfe, 0x1b, 0x2e, 0x0b, 0xe8, 0x26, 0xd6, 0x95, 0xa0, 0x00, 0x67, 0x1f, 0x44, 0xfc, 0x1a,
+        0x5c, 0x17, 0xd0, 0x4d, 0xad, 0x2a,
+    ];
+
+    fn written(samples: &[(i64, f64)]) -> Vec<u8> {
+        let mut c = XorChunkWriter::new();
+        for &(timestamp, value) in samples {
+            c.append(timestamp, value);
+        }
+        assert_eq!(c.len(), samples.len());
+        c.bytes()
+    }
+
+    fn read(chunk: &[u8]) -> Vec<(i64, f64)> {
+        let mut r = XorChunkIterator::new(chunk);
+        let samples = r.by_ref().collect();
+        assert_eq!(r.error(), None);
+        samples
+    }
+
+    #[test]
+    fn write_golden() {
+        assert_eq!(written(&samples()), GOLDEN.to_vec());
+    }
+
+    #[test]
+    fn read_golden() {
+        assert_eq!(XorChunkIterator::new(&GOLDEN).samples(), 9);
+        assert_eq!(read(&GOLDEN), samples());
+    }
+
+    #[test]
+    fn small_chunks() {
+        assert_eq!(written(&[]), vec![0x00, 0x00]);
+        assert_eq!(read(&[0x00, 0x00]), vec![]);
+
+        let golden = [0x00, 0x01, 0x09, 0x3f, 0xf0, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00];
+        assert_eq!(written(&[(-5, 1.0)]), golden.to_vec());
+        assert_eq!(read(&golden), vec![(-5, 1.0)]);
+    }
+
+    #[test]
+    fn bucket_edges() {
+        let mut samples = vec![(T, 0.0), (T + 10, 0.0)];
+        let mut delta = 10i64;
+        for &dod in [8192, -8191, 65536, -65535, 524288, -524287, 524289, -524288, 8193, -8192].iter() {
+            delta += dod;
+            let previous = samples.last().unwrap().0;
+            samples.push((previous + delta, dod as f64));
+        }
+
+        assert_eq!(read(&written(&samples)), samples);
+    }
+
+    #[test]
+    fn truncated() {
+        let mut r = XorChunkIterator::new(&GOLDEN[..30]);
+        assert_eq!(r.by_ref().count(), 5);
+        assert!(r.error().is_some());
+
+        let mut r = XorChunkIterator::new(&GOLDEN[..1]);
+        assert_eq!(r.next(), None);
+        assert_eq!(r.error(), Some(&DecodeError::TruncatedPayload { offset: 0 }));
+    }
+
+    #[test]
+    fn invalid_varint() {
+        let chunk = [0x00, 0x01, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x01];
+        let mut r = XorChunkIterator::new(&chunk);
+        assert_eq!(r.try_next(), Err(DecodeError::InvalidVarint { offset: 16 }));
+    }
+
+    #[test]
+    fn zigzag_round_trip() {
+        for &number in [0, 1, -1, 63, -64, i64::MAX, i64::MIN].iter() {
+            assert_eq!(unzigzag(zigzag(number)), number);
+        }
+        assert_eq!(zigzag(-1), 1);
+        assert_eq!(zigzag(1), 2);
+    }
+}
@@ -0,0 +1,408 @@
+//! Property tests: round trips of generated samples through every codec, and
+//! random bytes fed to every parser, which must return errors rather than
+//! panic. Cases come from a seeded generator, so failures are reproducible;
+//! the failing seed is part of the assertion message.
+
+use std::io::Cursor;
+
+use block_file::{self, BlockFile, ValueCodec};
+use double_stream::*;
+use double_stream_lead_trail::*;
+use dump::DumpIterator;
+use io_stream::{IoBitWriter, IoBitReader};
+use prometheus_chunk::{XorChunkWriter, XorChunkIterator};
+use restart_index::{IndexedStream, RestartIndex};
+use slice_stream::SliceReader;
+use time_and_value_stream::*;
+use timestamp_stream::*;
+use tsz::{TszWriter, TszIterator};
+use vec_stream::{VecWriter, VecReader, BufferedVecReader};
+
+const CASES: u64 = 200;
+
+/// A PCG style generator, with helpers for the values that tend to find bugs.
+struct Random {
+    state: u64,
+}
+
+impl Random {
+    fn new(seed: u64) -> Self {
+        let mut random = Random { state: seed };
+        random.next();
+        random
+    }
+
+    fn next(&mut self) -> u64 {
+        self.state = self.state.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+        let x = self.state;
+        (x ^ (x >> 33)).wrapping_mul(0xff51afd7ed558ccd) ^ (x >> 29)
+    }
+
+    fn below(&mut self, n: u64) -> u64 {
+        self.next() % n
+    }
+
+    fn bytes(&mut self, max: u64) -> Vec<u8> {
+        let len = self.below(max + 1);
+        (0..len).map(|_| self.next() as u8).collect()
+    }
+
+    /// Any bit pattern, weighted towards special values and values close to
+    /// `previous`, so that every kind of XOR window is exercised.
+    fn value(&mut self, previous: f64) -> f64 {
+        match self.below(12) {
+            0 => previous,
+            1 => f64::from_bits(previous.to_bits() ^ (1 << self.below(64))),
+            2 => f64::from_bits(previous.to_bits() ^ (self.next() >> self.below(64))),
+            3 => f64::from_bits(previous.to_bits() ^ (self.next() << self.below(64))),
+            // NaNs with arbitrary payloads and signs
+            4 => f64::from_bits(0x7FF0000000000000 | (self.next() & 0x800FFFFFFFFFFFFF) | 1),
+            // subnormals
+            5 => f64::from_bits((self.next() & 0x800FFFFFFFFFFFFF) >> self.below(52)),
+            6 => [0.0, -0.0, f64::INFINITY, f64::NEG_INFINITY, f64::MAX, f64::MIN, f64::MIN_POSITIVE, f64::EPSILON][self.below(8) as usize],
+            7 => self.below(1000) as f64,
+            _ => f64::from_bits(self.next()),
+        }
+    }
+
+    fn values(&mut self, max: u64) -> Vec<f64> {
+        let mut previous = 0.0;
+        (0..self.below(max + 1)).map(|_| {
+            previous = self.value(previous);
+            previous
+        }).collect()
+    }
+
+    /// The next delta between increasing timestamps: regular, jittery,
+    /// repeated or far apart.
+    fn delta(&mut self, previous: u64) -> u64 {
+        match self.below(32) {
+            0 => 0,
+            1 => {
+                let bits = self.below(64);
+                self.below(1 << bits)
+            },
+            2..=9 => (previous + self.below(3)).saturating_sub(1),
+            10..=19 => previous,
+            _ => 10 + self.below(3),
+        }
+    }
+
+    /// Increasing timestamps, starting within the first delta of
+    /// `header_time`. Deltas can be too large to encode with some formats.
+    fn timestamps(&mut self, header_time: u64, max: u64) -> Vec<u64> {
+        let mut timestamp = header_time + self.below(100);
+        let mut delta = 10;
+        (0..self.below(max + 1)).map(|i| {
+            if i > 0 {
+                delta = self.delta(delta);
+                timestamp = timestamp.saturating_add(delta);
+            }
+            timestamp
+        }).collect()
+    }
+}
+
+fn bits(values: &[f64]) -> Vec<u64> {
+    values.iter().map(|value| value.to_bits()).collect()
+}
+
+fn sample_bits(samples: &[(u64, f64)]) -> Vec<(u64, u64)> {
+    samples.iter().map(|&(timestamp, value)| (timestamp, value.to_bits())).collect()
+}
+
+fn formats() -> Vec<TimestampFormat> {
+    let mut formats = Vec::new();
+    for &version in [TimestampVersion::V1, TimestampVersion::V2].iter() {
+        for &precision in [TimestampPrecision::Seconds, TimestampPrecision::Milliseconds,
+                           TimestampPrecision::Microseconds, TimestampPrecision::Nanoseconds].iter() {
+            formats.push(TimestampFormat { version, precision, table: None });
+        }
+        formats.push(TimestampFormat { version, table: DodTable::new([1, 2, 63]), ..Default::default() });
+    }
+    formats
+}
+
+#[test]
+fn double_stream_round_trip() {
+    for seed in 0..CASES {
+        let values = Random::new(seed).values(300);
+        let mut w = VecWriter::new();
+        let mut c = DoubleStreamWriter::new();
+        for &value in values.iter() {
+            c.push(value, &mut w);
+        }
+
+        let mut r = DoubleStreamIterator::new(VecReader::new(&w.bit_vector, w.used_bits_last_elm));
+        let read: Vec<f64> = r.by_ref().collect();
+        assert_eq!(bits(&read), bits(&values), "seed {}", seed);
+        assert_eq!(r.error(), None, "seed {}", seed);
+    }
+}
+
+#[test]
+fn lead_trail_round_trip() {
+    for seed in 0..CASES {
+        let values = Random::new(seed).values(300);
+        let mut w = VecWriter::new();
+        let mut c = DoubleStreamLeadTrail::new();
+        for &value in values.iter() {
+            c.push(value, &mut w);
+        }
+
+        let mut r = DoubleStreamLeadTrailIterator::new(VecReader::new(&w.bit_vector, w.used_bits_last_elm));
+        let read: Vec<f64> = r.by_ref().collect();
+        assert_eq!(bits(&read), bits(&values), "seed {}", seed);
+        assert_eq!(r.error(), None, "seed {}", seed);
+    }
+}
+
+/// How many of `timestamps` a stream in `format` can hold: all of them up to
+/// the first delta over `i64::MAX`, or for V1, the first delta of deltas
+/// outside of `i32`. Timestamps start within the first delta.
+fn encodable(format: TimestampFormat, header_time: u64, timestamps: &[u64]) -> usize {
+    let mut previous = (header_time, None);
+    timestamps.iter().take_while(|&&timestamp| {
+        let delta = (timestamp - previous.0) as i128;
+        if let Some(previous_delta) = previous.1 {
+            let delta_of_deltas = delta - previous_delta;
+            let fits_v1 = delta_of_deltas >= i32::MIN as i128 && delta_of_deltas <= i32::MAX as i128;
+            if delta > i64::MAX as i128 || (format.version == TimestampVersion::V1 && !fits_v1) {
+                return false;
+            }
+        }
+        previous = (timestamp, Some(delta));
+        true
+    }).count()
+}
+
+#[test]
+fn timestamp_round_trip() {
+    for format in formats() {
+        let mut total = 0;
+        for seed in 0..CASES {
+            let mut random = Random::new(seed);
+            let header_time = random.next() >> 2;
+            let timestamps = random.timestamps(header_time, 300);
+
+            // the timestamps up to the first one the format can't encode
+            let mut w = VecWriter::new();
+            let mut c = TimestampStreamWriter::with_format(header_time, format);
+            let written: Vec<u64> = timestamps.iter().cloned().take_while(|&timestamp| c.try_push(timestamp, &mut w).is_ok()).collect();
+            assert_eq!(written.len(), encodable(format, header_time, &timestamps), "{:?}, seed {}", format, seed);
+            total += written.len();
+
+            let mut r = TimestampStreamIterator::with_format(VecReader::new(&w.bit_vector, w.used_bits_last_elm), header_time, format);
+            assert_eq!(r.by_ref().collect::<Vec<_>>(), written, "{:?}, seed {}", format, seed);
+            assert_eq!(r.error(), None, "{:?}, seed {}", format, seed);
+        }
+
+        // most deltas fit, so the round trips above can't pass on a few
+        // samples per case
+        assert!(total > CASES as usize * 25, "{:?}", format);
+    }
+}
+
+#[test]
+fn time_and_value_round_trip() {
+    for seed in 0..CASES {
+        let mut random = Random::new(seed);
+        let header_time = random.below(1 << 40);
+        let timestamps = random.timestamps(header_time, 300);
+        let values = random.values(300);
+
+        let mut w = VecWriter::new();
+        let mut c = TimeAndValueStream::new(header_time);
+        let samples: Vec<(u64, f64)> = timestamps.into_iter().zip(values)
+            .take_while(|&(timestamp, value)| c.try_push(timestamp, value, &mut w).is_ok())
+            .collect();
+
+        let r = TimeAndValueIterator::new(VecReader::new(&w.bit_vector, w.used_bits_last_elm), header_time);
+        assert_eq!(sample_bits(&r.collect::<Vec<_>>()), sample_bits(&samples), "seed {}", seed);
+
+        let r = TimeAndValueIterator::new(BufferedVecReader::new(&w.bit_vector, w.used_bits_last_elm), header_time);
+        assert_eq!(sample_bits(&r.collect::<Vec<_>>()), sample_bits(&samples), "seed {}", seed);
+
+        let bytes = w.to_bytes();
+        let r = TimeAndValueIterator::new(SliceReader::new(&bytes, w.len()), header_time);
+        assert_eq!(sample_bits(&r.collect::<Vec<_>>()), sample_bits(&samples), "seed {}", seed);
+
+        let mut r = TimeAndValueIterator::new(VecReader::new(&w.bit_vector, w.used_bits_last_elm), header_time);
+        let mut timestamps = vec![0; samples.len() + 1];
+        let mut values = vec![0.0; samples.len() + 1];
+        let count = r.decode_into(&mut timestamps, &mut values);
+        let decoded: Vec<(u64, f64)> = timestamps.into_iter().zip(values).take(count).collect();
+        assert_eq!(sample_bits(&decoded), sample_bits(&samples), "seed {}", seed);
+    }
+}
+
+#[test]
+fn io_stream_round_trip() {
+    for seed in 0..CASES / 10 {
+        let mut random = Random::new(seed);
+        let values = random.values(1000);
+
+        let mut w = IoBitWriter::new(Vec::new());
+        let mut c = DoubleStreamWriter::new();
+        for &value in values.iter() {
+            c.push(value, &mut w);
+        }
+        let num_bits = w.len();
+        let bytes = w.finish().unwrap();
+
+        let r = DoubleStreamIterator::new(IoBitReader::new(Cursor::new(bytes), num_bits));
+        assert_eq!(bits(&r.collect::<Vec<_>>()), bits(&values), "seed {}", seed);
+    }
+}
+
+#[test]
+fn block_file_round_trip() {
+    for seed in 0..CASES / 10 {
+        let mut random = Random::new(seed);
+        let header_time = random.below(1 << 40);
+        let timestamps = random.timestamps(header_time, 300);
+        let values = random.values(300);
+        let format = formats()[random.below(10) as usize];
+
+        for &codec in [ValueCodec::DoubleStream, ValueCodec::LeadTrail].iter() {
+            let mut data = VecWriter::new();
+            let mut count = 0;
+            {
+                let mut timestamp_writer = TimestampStreamWriter::with_format(header_time, format);
+                let mut double_writer = DoubleStreamWriter::new();
+                let mut lead_trail_writer = DoubleStreamLeadTrail::new();
+                for (&timestamp, &value) in timestamps.iter().zip(values.iter()) {
+                    if timestamp_writer.try_push(timestamp, &mut data).is_err() {
+                        break;
+                    }
+                    match codec {
+                        ValueCodec::DoubleStream => double_writer.push(value, &mut data),
+                        ValueCodec::LeadTrail => lead_trail_writer.push(value, &mut data),
+                    }
+                    count += 1;
+                }
+            }
+
+            let block = BlockFile { codec, format, header_time, count, data };
+            let mut file = Vec::new();
+            block_file::write_block(&mut file, &block).unwrap();
+            let read = block_file::read_block(&mut Cursor::new(file)).unwrap();
+            assert_eq!(read.format, format, "seed {}", seed);
+
+            let expected: Vec<(u64, f64)> = timestamps.iter().cloned().zip(values.iter().cloned()).take(count as usize).collect();
+            assert_eq!(sample_bits(&read.iter().collect::<Vec<_>>()), sample_bits(&expected), "seed {}", seed);
+        }
+    }
+}
+
+#[test]
+fn tsz_round_trip() {
+    for seed in 0..CASES {
+        let mut random = Random::new(seed);
+        let t0 = random.next() as u32;
+        let timestamps = random.timestamps(t0 as u64, 300);
+        let values = random.values(300);
+
+        let mut w = VecWriter::new();
+        let mut c = TszWriter::new(t0, &mut w);
+        let samples: Vec<(u64, f64)> = timestamps.into_iter().zip(values)
+            .take_while(|&(timestamp, value)| timestamp <= u32::MAX as u64 && c.try_push(timestamp as u32, value, &mut w).is_ok())
+            .collect();
+        c.finish(&mut w);
+
+        let mut r = TszIterator::new(VecReader::new(&w.bit_vector, w.used_bits_last_elm));
+        assert_eq!(sample_bits(&r.by_ref().collect::<Vec<_>>()), sample_bits(&samples), "seed {}", seed);
+        assert_eq!(r.error(), None, "seed {}", seed);
+    }
+}
+
+#[test]
+fn prometheus_chunk_round_trip() {
+    for seed in 0..CASES {
+        let mut random = Random::new(seed);
+        let header_time = random.next() as i64 >> 1;
+        let timestamps = random.timestamps(0, 300);
+        let values = random.values(300);
+
+        let mut c = XorChunkWriter::new();
+        let samples: Vec<(i64, f64)> = timestamps.into_iter().zip(values)
+            .map(|(timestamp, value)| (header_time.wrapping_add(timestamp as i64), value))
+            .collect();
+        for &(timestamp, value) in samples.iter() {
+            c.append(timestamp, value);
+        }
+
+        let bytes = c.bytes();
+        let mut r = XorChunkIterator::new(&bytes);
+        let read: Vec<(i64, u64)> = r.by_ref().map(|(timestamp, value)| (timestamp, value.to_bits())).collect();
+        let expected: Vec<(i64, u64)> = samples.iter().map(|&(timestamp, value)| (timestamp, value.to_bits())).collect();
+        assert_eq!(read, expected, "seed {}", seed);
+        assert_eq!(r.error(), None, "seed {}", seed);
+    }
+}
+
+#[test]
+fn restart_index_round_trip() {
+    for seed in 0..CASES / 10 {
+        let mut random = Random::new(seed);
+        let header_time = random.below(1 << 40);
+        let timestamps = random.timestamps(header_time, 1000);
+        let values = random.values(1000);
+
+        let mut w = VecWriter::new();
+        let mut c = IndexedStream::new(header_time, 1 + random.below(50) as usize);
+        let samples: Vec<(u64, f64)> = timestamps.into_iter().zip(values)
+            .take_while(|&(timestamp, value)| c.try_push(timestamp, value, &mut w).is_ok())
+            .collect();
+        let index = RestartIndex::from_bytes(&c.index().to_bytes()).unwrap();
+        assert_eq!(&index, c.index());
+
+        if let Some(&(target, _)) = samples.get(random.below(samples.len() as u64 + 1) as usize) {
+            let mut r = TimeAndValueIterator::new(VecReader::new(&w.bit_vector, w.used_bits_last_elm), header_time);
+            r.seek(&index, target);
+            let expected: Vec<(u64, f64)> = samples.iter().cloned().filter(|&(timestamp, _)| timestamp >= target).collect();
+            let read: Vec<(u64, f64)> = r.skip_while(|&(timestamp, _)| timestamp < target).collect();
+            assert_eq!(sample_bits(&read), sample_bits(&expected), "seed {}", seed);
+        }
+    }
+}
+
+/// Random bytes must decode to some samples and possibly an error, but
+/// never panic, whatever codec or reader they are given to.
+#[test]
+fn parsers_never_panic() {
+    for seed in 0..CASES * 10 {
+        let mut random = Random::new(seed);
+        let bytes = random.bytes(256);
+        let num_bits = random.below(bytes.len() as u64 * 8 + 1) as usize;
+        let header_time = random.next();
+        let format = formats()[random.below(10) as usize];
+
+        DoubleStreamIterator::new(SliceReader::new(&bytes, num_bits)).count();
+        DoubleStreamLeadTrailIterator::new(SliceReader::new(&bytes, num_bits)).count();
+        TimestampStreamIterator::with_format(SliceReader::new(&bytes, num_bits), header_time, format).count();
+        TimeAndValueIterator::new(SliceReader::new(&bytes, num_bits), header_time).count();
+        TimeAndValueIterator::with_decoder(SliceReader::new(&bytes, num_bits), header_time, DoubleStreamLeadTrailParser::new()).count();
+        TszIterator::new(SliceReader::new(&bytes, num_bits)).count();
+        DumpIterator::new(SliceReader::new(&bytes, num_bits), header_time).count();
+        XorChunkIterator::new(&bytes).count();
+
+        let words: Vec<u64> = bytes.chunks(8).map(|chunk| chunk.iter().fold(0, |word, &byte| word << 8 | byte as u64) << (64 - 8 * chunk.len())).collect();
+        let used_bits = random.below(65) as u8;
+        TimeAndValueIterator::new(VecReader::new(&words, used_bits), header_time).count();
+        TimeAndValueIterator::new(BufferedVecReader::new(&words, used_bits), header_time).count();
+        let mut r = TimeAndValueIterator::new(VecReader::new(&words, used_bits), header_time);
+        r.decode_into(&mut [0; 16], &mut [0.0; 16]);
+
+        if let Ok(block) = block_file::read_block(&mut Cursor::new(&bytes)) {
+            block.iter().count();
+        }
+
+        let mut index_bytes = 1u64.to_be_bytes().to_vec();
+        index_bytes.extend(bytes.iter().cycle().take(40));
+        let index = RestartIndex::from_bytes(&index_bytes).unwrap();
+        let mut r = TimeAndValueIterator::new(VecReader::new(&words, used_bits), header_time);
+        r.seek(&index, random.next());
+        r.count();
+    }
+}
@@ -0,0 +1,202 @@
+//! A buffer in front of a `TimeAndValueStream` for samples that arrive
+//! slightly out of order, such as batches resent after a retry. Samples are
+//! held until they are at least a grace period older than the newest
+//! timestamp seen, and are then written in order. Samples with the same
+//! timestamp are merged according to a `DuplicatePolicy`.
+//!
+//! Samples that arrive after a later one has already been written can't be
+//! encoded and are rejected with `EncodeError::OutOfOrder`.
+
+use std::collections::BTreeMap;
+use std::collections::btree_map::Entry;
+
+use time_and_value_stream::TimeAndValueStream;
+use stream::{Writer, ValueEncoder};
+use error::EncodeError;
+
+/// How to merge samples with the same timestamp.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum DuplicatePolicy {
+    #[default]
+    KeepFirst,
+    KeepLast,
+    Min,
+    Max,
+    Sum,
+}
+
+impl DuplicatePolicy {
+    fn merge(&self, old: f64, new: f64) -> f64 {
+        match *self {
+            DuplicatePolicy::KeepFirst => old,
+            DuplicatePolicy::KeepLast => new,
+            DuplicatePolicy::Min => old.min(new),
+            DuplicatePolicy::Max => old.max(new),
+            DuplicatePolicy::Sum => old + new,
+        }
+    }
+}
+
+pub struct ReorderBuffer {
+    grace: u64,
+    policy: DuplicatePolicy,
+    pending: BTreeMap<u64, f64>,
+    newest: Option<u64>,
+    written: Option<u64>,
+}
+
+impl ReorderBuffer {
+    /// Samples are held until they are at least `grace` older than the
+    /// newest timestamp seen, in the units of the timestamps. A `grace` of
+    /// zero releases every sample as soon as it is added.
+    pub fn new(grace: u64, policy: DuplicatePolicy) -> Self {
+        ReorderBuffer {
+            grace,
+            policy,
+            pending: BTreeMap::new(),
+            newest: None,
+            written: None,
+        }
+    }
+
+    /// Adds a sample to the buffer, or returns an error if a sample at or
+    /// after `timestamp` has already left it.
+    pub fn insert(&mut self, timestamp: u64, value: f64) -> Result<(), EncodeError> {
+        if let Some(previous) = self.written {
+            if timestamp <= previous {
+                return Err(EncodeError::OutOfOrder { timestamp, previous });
+            }
+        }
+
+        match self.pending.entry(timestamp) {
+            Entry::Vacant(entry) => { entry.insert(value); },
+            Entry::Occupied(mut entry) => {
+                let merged = self.policy.merge(*entry.get(), value);
+                entry.insert(merged);
+            },
+        }
+        self.newest = Some(self.newest.map_or(timestamp, |newest| newest.max(timestamp)));
+
+        Ok(())
+    }
+
+    /// Removes the oldest sample if it is past the grace period.
+    pub fn pop_ready(&mut self) -> Option<(u64, f64)> {
+        let newest = self.newest?;
+        let (&timestamp, _) = self.pending.iter().next()?;
+        if newest - timestamp < self.grace {
+            return None;
+        }
+        self.pop()
+    }
+
+    /// Removes the oldest sample, regardless of the grace period.
+    pub fn pop(&mut self) -> Option<(u64, f64)> {
+        let (timestamp, value) = self.pending.pop_first()?;
+        self.written = Some(timestamp);
+        Some((timestamp, value))
+    }
+
+    /// The number of samples held.
+    pub fn len(&self) -> usize {
+        self.pending.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pending.is_empty()
+    }
+
+    /// Adds a sample and writes all samples past the grace period to
+    /// `stream`. A sample that the stream rejects is dropped and its error
+    /// returned.
+    pub fn push<E>(&mut self, timestamp: u64, value: f64, stream: &mut TimeAndValueStream<E>, writer: &mut dyn Writer) -> Result<(), EncodeError>
+        where E: ValueEncoder {
+        self.insert(timestamp, value)?;
+        while let Some((timestamp, value)) = self.pop_ready() {
+            stream.try_push(timestamp, value, writer)?;
+        }
+        Ok(())
+    }
+
+    /// Writes all remaining samples to `stream`, such as before sealing a
+    /// block.
+    pub fn flush<E>(&mut self, stream: &mut TimeAndValueStream<E>, writer: &mut dyn Writer) -> Result<(), EncodeError>
+        where E: ValueEncoder {
+        while let Some((timestamp, value)) = self.pop() {
+            stream.try_push(timestamp, value, writer)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use time_and_value_stream::TimeAndValueIterator;
+    use vec_stream::{VecWriter, VecReader};
+
+    #[test]
+    fn reorders() {
+        let mut w = VecWriter::new();
+        let mut c = TimeAndValueStream::new(1000);
+        let mut b = ReorderBuffer::new(30, DuplicatePolicy::KeepFirst);
+
+        for &(timestamp, value) in [(1010, 1.0), (1030, 3.0), (1020, 2.0), (1050, 5.0), (1040, 4.0), (1060, 6.0)].iter() {
+            b.push(timestamp, value, &mut c, &mut w).unwrap();
+        }
+        // samples less than 30 older than 1060 are still held
+        assert_eq!(b.len(), 3);
+
+        b.flush(&mut c, &mut w).unwrap();
+        assert!(b.is_empty());
+
+        let r = TimeAndValueIterator::new(VecReader::new(&w.bit_vector, w.used_bits_last_elm), 1000);
+        assert_eq!(r.collect::<Vec<_>>(), vec![(1010, 1.0), (1020, 2.0), (1030, 3.0), (1040, 4.0), (1050, 5.0), (1060, 6.0)]);
+    }
+
+    #[test]
+    fn grace_boundary() {
+        let mut b = ReorderBuffer::new(10, DuplicatePolicy::KeepFirst);
+        b.insert(100, 1.0).unwrap();
+        b.insert(109, 2.0).unwrap();
+        assert_eq!(b.pop_ready(), None);
+
+        // exactly `grace` older is past the grace period
+        b.insert(110, 3.0).unwrap();
+        assert_eq!(b.pop_ready(), Some((100, 1.0)));
+        assert_eq!(b.pop_ready(), None);
+        assert_eq!(b.len(), 2);
+    }
+
+    #[test]
+    fn late_samples() {
+        let mut b = ReorderBuffer::new(10, DuplicatePolicy::KeepFirst);
+        b.insert(100, 1.0).unwrap();
+        b.insert(120, 2.0).unwrap();
+        assert_eq!(b.pop_ready(), Some((100, 1.0)));
+        assert_eq!(b.pop_ready(), None);
+        assert_eq!(b.pop(), Some((120, 2.0)));
+
+        assert_eq!(b.insert(115, 3.0), Err(EncodeError::OutOfOrder { timestamp: 115, previous: 120 }));
+        assert_eq!(b.insert(120, 3.0), Err(EncodeError::OutOfOrder { timestamp: 120, previous: 120 }));
+        b.insert(121, 3.0).unwrap();
+    }
+
+    #[test]
+    fn duplicates() {
+        let merged = |policy| {
+            let mut b = ReorderBuffer::new(0, policy);
+            b.insert(10, 2.0).unwrap();
+            b.insert(10, 5.0).unwrap();
+            b.insert(10, 1.0).unwrap();
+            assert_eq!(b.len(), 1);
+            b.pop().unwrap().1
+        };
+
+        assert_eq!(merged(DuplicatePolicy::KeepFirst), 2.0);
+        assert_eq!(merged(DuplicatePolicy::KeepLast), 1.0);
+        assert_eq!(merged(DuplicatePolicy::Min), 1.0);
+        assert_eq!(merged(DuplicatePolicy::Max), 5.0);
+        assert_eq!(merged(DuplicatePolicy::Sum), 8.0);
+    }
+}
@@ -0,0 +1,254 @@
+//! A restart index records the encoder state every `interval` samples, next
+//! to the bit offset it was recorded at. Decoding can resume from any restart
+//! point, so reading the end of a long block doesn't require decoding it from
+//! the start. See `TimeAndValueIterator::seek`.
+//!
+//! The index is kept beside the stream rather than in it, so streams written
+//! with an index are unchanged and can still be read without one. It only
+//! supports streams with values compressed by `DoubleStreamWriter`.
+
+use time_and_value_stream::TimeAndValueStream;
+use timestamp_stream::{TimestampStreamWriter, TimestampStreamState, TimestampFormat, EncodeError};
+use double_stream::{DoubleStreamWriter, DoubleStreamState};
+use stream::Writer;
+
+const POINT_SIZE: usize = 5 * 8;
+
+/// The state of a stream right after a sample was written.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RestartPoint {
+    /// The offset in bits of the next sample.
+    pub bit_offset: usize,
+    pub timestamp: u64,
+    pub delta: i64,
+    /// The bits of the value.
+    pub value: u64,
+    pub xor: u64,
+}
+
+impl RestartPoint {
+    pub fn timestamp_state(&self) -> TimestampStreamState {
+        TimestampStreamState::Following { value: self.timestamp, delta: self.delta }
+    }
+
+    pub fn value_state(&self) -> DoubleStreamState {
+        DoubleStreamState::Following { value: self.value, xor: self.xor }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RestartIndex {
+    interval: usize,
+    points: Vec<RestartPoint>,
+}
+
+impl RestartIndex {
+    /// Panics if `interval` is zero.
+    pub fn new(interval: usize) -> Self {
+        assert!(interval > 0);
+
+        RestartIndex {
+            interval,
+            points: Vec::new(),
+        }
+    }
+
+    pub fn interval(&self) -> usize {
+        self.interval
+    }
+
+    /// The restart points, oldest first.
+    pub fn points(&self) -> &[RestartPoint] {
+        &self.points
+    }
+
+    /// The last restart point with a timestamp before `timestamp`, so that no
+    /// sample at or after `timestamp` is skipped when restarting there.
+    pub fn restart_point(&self, timestamp: u64) -> Option<&RestartPoint> {
+        let count = self.points.partition_point(|point| point.timestamp < timestamp);
+        count.checked_sub(1).map(|i| &self.points[i])
+    }
+
+    /// The interval followed by each point's bit offset, timestamp, delta,
+    /// value and XOR, all as big-endian 64-bit numbers.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(8 + self.points.len() * POINT_SIZE);
+        bytes.extend_from_slice(&(self.interval as u64).to_be_bytes());
+        for point in &self.points {
+            bytes.extend_from_slice(&(point.bit_offset as u64).to_be_bytes());
+            bytes.extend_from_slice(&point.timestamp.to_be_bytes());
+            bytes.extend_from_slice(&point.delta.to_be_bytes());
+            bytes.extend_from_slice(&point.value.to_be_bytes());
+            bytes.extend_from_slice(&point.xor.to_be_bytes());
+        }
+        bytes
+    }
+
+    /// Returns `None` if `bytes` is not an index written by `to_bytes`.
+    pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        if bytes.len() < 8 || (bytes.len() - 8) % POINT_SIZE != 0 {
+            return None;
+        }
+
+        let mut words = bytes.chunks(8).map(|chunk| {
+            let mut word = [0u8; 8];
+            word.copy_from_slice(chunk);
+            u64::from_be_bytes(word)
+        });
+
+        let interval = words.next()? as usize;
+        if interval == 0 {
+            return None;
+        }
+
+        let mut points = Vec::with_capacity((bytes.len() - 8) / POINT_SIZE);
+        while let Some(bit_offset) = words.next() {
+            points.push(RestartPoint {
+                bit_offset: bit_offset as usize,
+                timestamp: words.next()?,
+                delta: words.next()? as i64,
+                value: words.next()?,
+                xor: words.next()?,
+            });
+        }
+
+        Some(RestartIndex { interval, points })
+    }
+}
+
+/// A `TimeAndValueStream` that records a restart point every `interval`
+/// samples.
+pub struct IndexedStream {
+    stream: TimeAndValueStream,
+    index: RestartIndex,
+    count: usize,
+}
+
+impl IndexedStream {
+    pub fn new(header_time: u64, interval: usize) -> Self {
+        IndexedStream::with_format(header_time, TimestampFormat::default(), interval)
+    }
+
+    pub fn with_format(header_time: u64, format: TimestampFormat, interval: usize) -> Self {
+        IndexedStream {
+            stream: TimeAndValueStream::from_parts(TimestampStreamWriter::with_format(header_time, format), DoubleStreamWriter::new()),
+            index: RestartIndex::new(interval),
+            count: 0,
+        }
+    }
+
+    /// Panics if the timestamp can't be encoded. See `try_push`.
+    pub fn push(&mut self, timestamp: u64, number: f64, writer: &mut dyn Writer) {
+        if let Err(error) = self.try_push(timestamp, number, writer) {
+            panic!("{}", error);
+        }
+    }
+
+    /// See `TimeAndValueStream::try_push`.
+    pub fn try_push(&mut self, timestamp: u64, number: f64, writer: &mut dyn Writer) -> Result<(), EncodeError> {
+        self.stream.try_push(timestamp, number, writer)?;
+        self.count += 1;
+
+        if self.count % self.index.interval == 0 {
+            if let (TimestampStreamState::Following { value: timestamp, delta }, DoubleStreamState::Following { value, xor }) =
+                (self.stream.timestamps().state(), self.stream.values().state()) {
+                self.index.points.push(RestartPoint { bit_offset: writer.position(), timestamp, delta, value, xor });
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn index(&self) -> &RestartIndex {
+        &self.index
+    }
+
+    pub fn into_index(self) -> RestartIndex {
+        self.index
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use time_and_value_stream::TimeAndValueIterator;
+    use vec_stream::{VecWriter, VecReader};
+    use slice_stream::SliceReader;
+
+    fn samples() -> Vec<(u64, f64)> {
+        (0..1_000u64).map(|i| (1496361600 + i * 60 + i % 7, (i as f64 / 10.0).sin())).collect()
+    }
+
+    fn indexed(interval: usize) -> (VecWriter, RestartIndex) {
+        let mut w = VecWriter::new();
+        let mut c = IndexedStream::new(1496361600, interval);
+        for (timestamp, value) in samples() {
+            c.push(timestamp, value, &mut w);
+        }
+        (w, c.into_index())
+    }
+
+    #[test]
+    fn records_points() {
+        let (w, index) = indexed(100);
+        assert_eq!(index.points().len(), 10);
+        assert_eq!(index.points()[0].timestamp, samples()[99].0);
+        assert_eq!(index.points()[9].bit_offset, w.len());
+
+        assert_eq!(index.restart_point(samples()[99].0), None);
+        assert_eq!(index.restart_point(samples()[99].0 + 1), Some(&index.points()[0]));
+        assert_eq!(index.restart_point(samples()[250].0).unwrap().timestamp, samples()[199].0);
+    }
+
+    #[test]
+    fn seek() {
+        let (w, index) = indexed(100);
+        let target = samples()[555].0;
+
+        let mut r = TimeAndValueIterator::new(VecReader::new(&w.bit_vector, w.used_bits_last_elm), 1496361600);
+        r.seek(&index, target);
+        assert_eq!(r.collect::<Vec<_>>(), samples()[500..].to_vec());
+
+        let bytes = w.to_bytes();
+        let mut r = TimeAndValueIterator::new(SliceReader::new(&bytes, w.len()), 1496361600);
+        r.seek(&index, target);
+        assert_eq!(r.skip_while(|&(timestamp, _)| timestamp < target).collect::<Vec<_>>(), samples()[555..].to_vec());
+    }
+
+    #[test]
+    fn seek_only_forward() {
+        let (w, index) = indexed(100);
+
+        // before the first point
+        let mut r = TimeAndValueIterator::new(VecReader::new(&w.bit_vector, w.used_bits_last_elm), 1496361600);
+        r.seek(&index, samples()[10].0);
+        assert_eq!(r.next(), Some(samples()[0]));
+
+        let mut r = TimeAndValueIterator::new(VecReader::new(&w.bit_vector, w.used_bits_last_elm), 1496361600);
+        r.seek(&index, samples()[900].0);
+        r.seek(&index, samples()[300].0);
+        assert_eq!(r.next(), Some(samples()[900]));
+    }
+
+    #[test]
+    fn restart_from_every_point() {
+        let (w, index) = indexed(7);
+        for (i, point) in index.points().iter().enumerate() {
+            let mut r = TimeAndValueIterator::new(VecReader::new(&w.bit_vector, w.used_bits_last_elm), 1496361600);
+            r.seek(&index, point.timestamp + 1);
+            assert_eq!(r.next(), samples().get((i + 1) * 7).cloned());
+        }
+    }
+
+    #[test]
+    fn bytes_round_trip() {
+        let (_, index) = indexed(100);
+        let bytes = index.to_bytes();
+        assert_eq!(bytes.len(), 8 + 10 * POINT_SIZE);
+        assert_eq!(RestartIndex::from_bytes(&bytes), Some(index));
+
+        assert_eq!(RestartIndex::from_bytes(&bytes[..bytes.len() - 1]), None);
+        assert_eq!(RestartIndex::from_bytes(&[0; 8]), None);
+        assert_eq!(RestartIndex::from_bytes(&[]), None);
+    }
+}
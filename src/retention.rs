@@ -0,0 +1,222 @@
+//! Retention policies limit how much data is kept in memory, like the 26 hours
+//! kept by Gorilla. Data is evicted a sealed block at a time, oldest first,
+//! either when all of its samples are older than a maximum age or while the
+//! total size is over a maximum. Open blocks are never evicted, but count
+//! towards the size.
+//!
+//! Evicted blocks are handed to a callback, which can for example write them
+//! to disk with `block_file::write_block`, or just drop them.
+
+use series::{Series, SealedBlock};
+use ts_map::{TsMap, TsEntry};
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct RetentionPolicy {
+    /// Evict blocks whose samples are all older than this, in the units of
+    /// the timestamps.
+    pub max_age: Option<u64>,
+    /// Evict blocks while the total size, as given by `Series::size_bytes`,
+    /// is over this.
+    pub max_bytes: Option<usize>,
+}
+
+/// What an eviction pass removed.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct EvictionStats {
+    pub blocks: usize,
+    /// The sum of `SealedBlock::size_bytes` of the evicted blocks.
+    pub bytes: usize,
+}
+
+impl EvictionStats {
+    fn add(&mut self, other: EvictionStats) {
+        self.blocks += other.blocks;
+        self.bytes += other.bytes;
+    }
+}
+
+impl RetentionPolicy {
+    /// Applies the policy to a single series, where `now` is the current
+    /// time in the units of its timestamps.
+    pub fn apply(&self, series: &mut Series, now: u64, on_evict: &mut dyn FnMut(SealedBlock)) -> EvictionStats {
+        let mut size = series.size_bytes();
+        let mut count = 0;
+        for block in series.sealed_blocks() {
+            let over_size = self.max_bytes.is_some_and(|max| size > max);
+            if !self.is_expired(block, now) && !over_size {
+                break;
+            }
+            size -= block.size_bytes();
+            count += 1;
+        }
+
+        evict(series, count, on_evict)
+    }
+
+    /// Applies the maximum age to every series in `map`, and the maximum size
+    /// to all of them together, evicting the oldest blocks of any series
+    /// first. `on_evict` is called while the series is locked.
+    pub fn apply_to_map(&self, map: &TsMap, now: u64, on_evict: &mut dyn FnMut(&TsEntry, SealedBlock)) -> EvictionStats {
+        let entries = map.snapshot();
+        let mut stats = EvictionStats::default();
+
+        let by_age = RetentionPolicy { max_age: self.max_age, max_bytes: None };
+        for entry in &entries {
+            stats.add(by_age.apply(&mut entry.lock(), now, &mut |block| on_evict(entry, block)));
+        }
+
+        if let Some(max) = self.max_bytes {
+            let mut size = 0;
+            let mut blocks = Vec::new();
+            for (i, entry) in entries.iter().enumerate() {
+                let series = entry.lock();
+                size += series.size_bytes();
+                blocks.extend(series.sealed_blocks().iter().map(|block| (block_key(block), i, block.size_bytes())));
+            }
+            blocks.sort();
+
+            // the newest block to evict from each series, rechecked under the
+            // lock below: another pass may have evicted some blocks since
+            let mut newest = vec![None; entries.len()];
+            for (key, i, block_size) in blocks {
+                if size <= max {
+                    break;
+                }
+                size -= block_size;
+                newest[i] = Some(key);
+            }
+
+            for (entry, newest) in entries.iter().zip(newest) {
+                if let Some(newest) = newest {
+                    let mut series = entry.lock();
+                    let count = series.sealed_blocks().iter().take_while(|&block| block_key(block) <= newest).count();
+                    stats.add(evict(&mut series, count, &mut |block| on_evict(entry, block)));
+                }
+            }
+        }
+
+        stats
+    }
+
+    fn is_expired(&self, block: &SealedBlock, now: u64) -> bool {
+        self.max_age.is_some_and(|max_age| block.last_timestamp() < now.saturating_sub(max_age))
+    }
+}
+
+/// Orders the sealed blocks of a series. The header time alone isn't enough,
+/// as sealing early can leave two blocks for the same window.
+fn block_key(block: &SealedBlock) -> (u64, u64) {
+    (block.header_time(), block.last_timestamp())
+}
+
+fn evict(series: &mut Series, count: usize, on_evict: &mut dyn FnMut(SealedBlock)) -> EvictionStats {
+    let mut stats = EvictionStats::default();
+    for block in series.remove_oldest(count) {
+        stats.blocks += 1;
+        stats.bytes += block.size_bytes();
+        on_evict(block);
+    }
+    stats
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use std::thread;
+
+    fn series(window: u64, samples: u64) -> Series {
+        let mut s = Series::with_window(window);
+        for i in 0..samples {
+            s.push(i * 10, i as f64).unwrap();
+        }
+        s
+    }
+
+    #[test]
+    fn max_age() {
+        let mut s = series(100, 50);
+        assert_eq!(s.sealed_blocks().len(), 4);
+
+        let policy = RetentionPolicy { max_age: Some(250), ..Default::default() };
+        let mut evicted = Vec::new();
+        let stats = policy.apply(&mut s, 460, &mut |block| evicted.push(block.header_time()));
+
+        // the block from 100 to 190 is entirely older than 210
+        assert_eq!(evicted, vec![0, 100]);
+        assert_eq!(stats.blocks, 2);
+        assert_eq!(s.sealed_blocks()[0].header_time(), 200);
+        assert_eq!(s.iter().next(), Some((200, 20.0)));
+
+        assert_eq!(policy.apply(&mut s, 460, &mut |_| {}), EvictionStats::default());
+    }
+
+    #[test]
+    fn max_bytes() {
+        let mut s = series(100, 50);
+        let sizes: Vec<usize> = s.sealed_blocks().iter().map(|block| block.size_bytes()).collect();
+        let total = s.size_bytes();
+
+        let policy = RetentionPolicy { max_bytes: Some(total - sizes[0]), ..Default::default() };
+        let stats = policy.apply(&mut s, 0, &mut |_| {});
+        assert_eq!(stats, EvictionStats { blocks: 1, bytes: sizes[0] });
+        assert_eq!(s.size_bytes(), total - sizes[0]);
+
+        // the open block is never evicted
+        let policy = RetentionPolicy { max_bytes: Some(0), ..Default::default() };
+        let stats = policy.apply(&mut s, 0, &mut |_| {});
+        assert_eq!(stats.blocks, 3);
+        assert!(s.sealed_blocks().is_empty());
+        assert_eq!(s.iter().count(), 10);
+    }
+
+    #[test]
+    fn map() {
+        let map = TsMap::with_format(100, Default::default());
+        for i in 0..50 {
+            map.push("a", i * 10, 1.0).unwrap();
+            map.push("b", 100 + i * 10, 2.0).unwrap();
+        }
+
+        let policy = RetentionPolicy { max_age: Some(300), ..Default::default() };
+        let mut evicted = Vec::new();
+        policy.apply_to_map(&map, 500, &mut |entry, block| evicted.push((entry.name().to_string(), block.header_time())));
+        evicted.sort();
+        assert_eq!(evicted, vec![("a".to_string(), 0), ("a".to_string(), 100), ("b".to_string(), 100)]);
+
+        // the oldest blocks go first, whichever series they are in
+        let total: usize = map.snapshot().iter().map(|entry| entry.lock().size_bytes()).sum();
+        let policy = RetentionPolicy { max_bytes: Some(total - 1), ..Default::default() };
+        let mut evicted = Vec::new();
+        let stats = policy.apply_to_map(&map, 500, &mut |entry, block| evicted.push((entry.name().to_string(), block.header_time())));
+        assert_eq!(evicted, vec![("a".to_string(), 200)]);
+        assert_eq!(stats.blocks, 1);
+        assert!(stats.bytes > 0);
+    }
+
+    #[test]
+    fn concurrent_map() {
+        for _ in 0..20 {
+            let map = Arc::new(TsMap::with_format(100, Default::default()));
+            for i in 0..50 {
+                map.push("a", i * 10, 1.0).unwrap();
+                map.push("b", i * 10, 2.0).unwrap();
+            }
+            let sizes: Vec<usize> = map.get("a").unwrap().lock().sealed_blocks().iter().map(|block| block.size_bytes()).collect();
+            let total: usize = map.snapshot().iter().map(|entry| entry.lock().size_bytes()).sum();
+
+            // both passes see the two oldest blocks as over the limit, but
+            // only one may evict each of them
+            let policy = RetentionPolicy { max_bytes: Some(total - sizes[0] - 1), ..Default::default() };
+            let threads: Vec<_> = (0..2).map(|_| {
+                let map = map.clone();
+                thread::spawn(move || policy.apply_to_map(&map, 0, &mut |_, _| {}).blocks)
+            }).collect();
+            let evicted: usize = threads.into_iter().map(|thread| thread.join().unwrap()).sum();
+
+            assert_eq!(evicted, 2);
+            assert_eq!(map.get("a").unwrap().lock().sealed_blocks().len(), 3);
+            assert_eq!(map.get("b").unwrap().lock().sealed_blocks().len(), 3);
+        }
+    }
+}
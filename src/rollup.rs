@@ -0,0 +1,180 @@
+//! Downsampling of samples into fixed width time buckets. Each bucket is
+//! summarized by a `Rollup`, holding the min, max, sum, count, first and last
+//! value of the samples in it.
+//!
+//! Rollups can be written back into a `TimeAndValueStream`, one stream per
+//! aggregate, so charts over long periods don't need to decode the raw data.
+
+use time_and_value_stream::TimeAndValueStream;
+use stream::{Writer, ValueEncoder};
+use error::EncodeError;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Aggregate {
+    Min,
+    Max,
+    Sum,
+    Count,
+    Mean,
+    First,
+    Last,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Rollup {
+    /// The start of the bucket, a multiple of the bucket width.
+    pub start: u64,
+    pub min: f64,
+    pub max: f64,
+    pub sum: f64,
+    pub count: u64,
+    pub first: f64,
+    pub last: f64,
+}
+
+impl Rollup {
+    pub fn new(start: u64, value: f64) -> Self {
+        Rollup {
+            start,
+            min: value,
+            max: value,
+            sum: value,
+            count: 1,
+            first: value,
+            last: value,
+        }
+    }
+
+    pub fn add(&mut self, value: f64) {
+        self.min = self.min.min(value);
+        self.max = self.max.max(value);
+        self.sum += value;
+        self.count += 1;
+        self.last = value;
+    }
+
+    pub fn mean(&self) -> f64 {
+        self.sum / self.count as f64
+    }
+
+    pub fn get(&self, aggregate: Aggregate) -> f64 {
+        match aggregate {
+            Aggregate::Min => self.min,
+            Aggregate::Max => self.max,
+            Aggregate::Sum => self.sum,
+            Aggregate::Count => self.count as f64,
+            Aggregate::Mean => self.mean(),
+            Aggregate::First => self.first,
+            Aggregate::Last => self.last,
+        }
+    }
+}
+
+/// Groups the samples of an iterator into buckets of `width`, yielding a
+/// `Rollup` per non-empty bucket. Timestamps are expected to be in order; a
+/// timestamp going back to an earlier bucket starts a new rollup.
+pub struct RollupIterator<I> {
+    inner: I,
+    width: u64,
+    current: Option<Rollup>,
+}
+
+impl<I> RollupIterator<I> where I: Iterator<Item = (u64, f64)> {
+    /// Panics if `width` is zero.
+    pub fn new(inner: I, width: u64) -> Self {
+        assert!(width > 0);
+
+        RollupIterator {
+            inner,
+            width,
+            current: None,
+        }
+    }
+}
+
+impl<I> Iterator for RollupIterator<I> where I: Iterator<Item = (u64, f64)> {
+    type Item = Rollup;
+
+    fn next(&mut self) -> Option<Rollup> {
+        for (timestamp, value) in self.inner.by_ref() {
+            let start = timestamp - timestamp % self.width;
+            match self.current {
+                Some(ref mut rollup) if rollup.start == start => rollup.add(value),
+                _ => {
+                    if let Some(rollup) = self.current.replace(Rollup::new(start, value)) {
+                        return Some(rollup);
+                    }
+                }
+            }
+        }
+
+        self.current.take()
+    }
+}
+
+/// See `RollupIterator`.
+pub fn rollup<I>(samples: I, width: u64) -> RollupIterator<I::IntoIter> where I: IntoIterator<Item = (u64, f64)> {
+    RollupIterator::new(samples.into_iter(), width)
+}
+
+/// Encodes one aggregate of each rollup into `stream`, timestamped with the
+/// start of its bucket. Stops at the first rollup that can't be encoded.
+pub fn write_rollups<I, E>(rollups: I, aggregate: Aggregate, stream: &mut TimeAndValueStream<E>, writer: &mut dyn Writer) -> Result<(), EncodeError>
+    where I: IntoIterator<Item = Rollup>, E: ValueEncoder {
+    for rollup in rollups {
+        stream.try_push(rollup.start, rollup.get(aggregate), writer)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use time_and_value_stream::TimeAndValueIterator;
+    use vec_stream::{VecWriter, VecReader};
+
+    #[test]
+    fn buckets() {
+        let samples = vec![(0, 1.0), (5, 3.0), (9, 2.0), (25, -1.0), (29, 4.0), (30, 6.0)];
+        let rollups: Vec<Rollup> = rollup(samples, 10).collect();
+
+        assert_eq!(rollups, vec![
+            Rollup { start: 0, min: 1.0, max: 3.0, sum: 6.0, count: 3, first: 1.0, last: 2.0 },
+            Rollup { start: 20, min: -1.0, max: 4.0, sum: 3.0, count: 2, first: -1.0, last: 4.0 },
+            Rollup { start: 30, min: 6.0, max: 6.0, sum: 6.0, count: 1, first: 6.0, last: 6.0 },
+        ]);
+        assert_eq!(rollups[0].mean(), 2.0);
+        assert_eq!(rollups[1].get(Aggregate::Mean), 1.5);
+        assert_eq!(rollups[1].get(Aggregate::Count), 2.0);
+    }
+
+    #[test]
+    fn empty() {
+        assert_eq!(rollup(vec![], 10).next(), None);
+    }
+
+    #[test]
+    fn out_of_order() {
+        let starts: Vec<u64> = rollup(vec![(15, 1.0), (5, 2.0), (16, 3.0)], 10).map(|rollup| rollup.start).collect();
+        assert_eq!(starts, vec![10, 0, 10]);
+    }
+
+    #[test]
+    fn write_back() {
+        // a day of samples every ten seconds, rolled up per hour
+        let header_time = 1496361600;
+        let samples: Vec<(u64, f64)> = (0..8640u64).map(|i| (header_time + i * 10, (i % 360) as f64)).collect();
+        let rollups: Vec<Rollup> = rollup(samples.iter().cloned(), 3600).collect();
+        assert_eq!(rollups.len(), 24);
+
+        for &aggregate in [Aggregate::Min, Aggregate::Max, Aggregate::Mean, Aggregate::Count].iter() {
+            let mut w = VecWriter::new();
+            let mut c = TimeAndValueStream::new(header_time);
+            write_rollups(rollups.iter().cloned(), aggregate, &mut c, &mut w).unwrap();
+
+            let r = TimeAndValueIterator::new(VecReader::new(&w.bit_vector, w.used_bits_last_elm), header_time);
+            let expected: Vec<(u64, f64)> = rollups.iter().map(|rollup| (rollup.start, rollup.get(aggregate))).collect();
+            assert_eq!(r.collect::<Vec<_>>(), expected);
+        }
+    }
+}
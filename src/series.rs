@@ -0,0 +1,368 @@
+//! A series stores samples in blocks, each covering a fixed window of time
+//! (two hours by default), as described in the Gorilla paper. Samples are
+//! appended to the open block. When a timestamp crosses the end of its
+//! window, the open block is sealed and a new one is opened with its header
+//! time aligned to the window. Sealed blocks are immutable.
+
+use time_and_value_stream::*;
+use timestamp_stream::*;
+use double_stream::*;
+use vec_stream::{VecWriter, VecReader};
+use std::vec::Drain;
+
+/// Two hours in seconds.
+pub const DEFAULT_WINDOW: u64 = 2 * 60 * 60;
+
+pub struct OpenBlock {
+    header_time: u64,
+    last_timestamp: u64,
+    count: usize,
+    format: TimestampFormat,
+    stream: TimeAndValueStream,
+    data: VecWriter,
+}
+
+impl OpenBlock {
+    fn new(header_time: u64, format: TimestampFormat) -> Self {
+        OpenBlock {
+            header_time,
+            last_timestamp: header_time,
+            count: 0,
+            format,
+            stream: TimeAndValueStream::from_parts(TimestampStreamWriter::with_format(header_time, format), DoubleStreamWriter::new()),
+            data: VecWriter::new(),
+        }
+    }
+
+    fn push(&mut self, timestamp: u64, value: f64) -> Result<(), EncodeError> {
+        self.stream.try_push(timestamp, value, &mut self.data)?;
+        self.last_timestamp = timestamp;
+        self.count += 1;
+        Ok(())
+    }
+
+    fn seal(self) -> SealedBlock {
+        SealedBlock {
+            header_time: self.header_time,
+            last_timestamp: self.last_timestamp,
+            count: self.count,
+            format: self.format,
+            data: self.data,
+        }
+    }
+
+    pub fn header_time(&self) -> u64 {
+        self.header_time
+    }
+
+    pub fn last_timestamp(&self) -> u64 {
+        self.last_timestamp
+    }
+
+    pub fn count(&self) -> usize {
+        self.count
+    }
+
+    pub fn data(&self) -> &VecWriter {
+        &self.data
+    }
+
+    /// The size of the compressed samples, rounded up to whole bytes.
+    pub fn size_bytes(&self) -> usize {
+        self.data.len().div_ceil(8)
+    }
+
+    pub fn iter(&self) -> TimeAndValueIterator<VecReader<'_>> {
+        block_iterator(&self.data, self.header_time, self.format)
+    }
+
+    /// The samples with timestamps in `start..end`.
+    pub fn range(&self, start: u64, end: u64) -> RangeIterator<TimeAndValueIterator<VecReader<'_>>> {
+        self.iter().range(start, end)
+    }
+}
+
+pub struct SealedBlock {
+    header_time: u64,
+    last_timestamp: u64,
+    count: usize,
+    format: TimestampFormat,
+    data: VecWriter,
+}
+
+impl SealedBlock {
+    pub fn header_time(&self) -> u64 {
+        self.header_time
+    }
+
+    pub fn last_timestamp(&self) -> u64 {
+        self.last_timestamp
+    }
+
+    pub fn count(&self) -> usize {
+        self.count
+    }
+
+    pub fn format(&self) -> TimestampFormat {
+        self.format
+    }
+
+    pub fn data(&self) -> &VecWriter {
+        &self.data
+    }
+
+    /// The size of the compressed samples, rounded up to whole bytes.
+    pub fn size_bytes(&self) -> usize {
+        self.data.len().div_ceil(8)
+    }
+
+    pub fn iter(&self) -> TimeAndValueIterator<VecReader<'_>> {
+        block_iterator(&self.data, self.header_time, self.format)
+    }
+
+    /// The samples with timestamps in `start..end`.
+    pub fn range(&self, start: u64, end: u64) -> RangeIterator<TimeAndValueIterator<VecReader<'_>>> {
+        self.iter().range(start, end)
+    }
+}
+
+fn block_iterator(data: &VecWriter, header_time: u64, format: TimestampFormat) -> TimeAndValueIterator<VecReader<'_>> {
+    TimeAndValueIterator::from_parts(
+        VecReader::new(&data.bit_vector, data.used_bits_last_elm),
+        TimestampStreamParser::with_format(header_time, format),
+        DoubleStreamParser::new())
+}
+
+pub struct Series {
+    window: u64,
+    format: TimestampFormat,
+    open: Option<OpenBlock>,
+    sealed: Vec<SealedBlock>,
+}
+
+/// Panics unless blocks of `window` can be stored in `format`. See
+/// `Series::with_format`.
+pub(crate) fn assert_window(window: u64, format: TimestampFormat) {
+    assert!(window > 0);
+    assert!(window - 1 < (1 << format.first_delta_bits()));
+}
+
+impl Series {
+    pub fn new() -> Self {
+        Series::with_window(DEFAULT_WINDOW)
+    }
+
+    pub fn with_window(window: u64) -> Self {
+        Series::with_format(window, TimestampFormat::default())
+    }
+
+    /// Panics if `window` is zero or too long for the first delta of a block
+    /// to be stored in `format`.
+    pub fn with_format(window: u64, format: TimestampFormat) -> Self {
+        assert_window(window, format);
+
+        Series {
+            window,
+            format,
+            open: None,
+            sealed: Vec::new(),
+        }
+    }
+
+    pub fn window(&self) -> u64 {
+        self.window
+    }
+
+    pub fn format(&self) -> TimestampFormat {
+        self.format
+    }
+
+    /// Appends a sample to the open block, sealing it first if `timestamp` is
+    /// past its window. Timestamps must not decrease.
+    pub fn push(&mut self, timestamp: u64, value: f64) -> Result<(), EncodeError> {
+        if let Some(previous) = self.last_timestamp() {
+            if timestamp < previous {
+                return Err(EncodeError::OutOfOrder { timestamp, previous });
+            }
+        }
+
+        let crossed_window = match self.open {
+            Some(ref block) => timestamp - block.header_time >= self.window,
+            None => true,
+        };
+
+        if crossed_window {
+            self.seal();
+            let header_time = timestamp - timestamp % self.window;
+            self.open = Some(OpenBlock::new(header_time, self.format));
+        }
+
+        self.open.as_mut().unwrap().push(timestamp, value)
+    }
+
+    /// Seals the open block, if any.
+    pub fn seal(&mut self) {
+        if let Some(block) = self.open.take() {
+            self.sealed.push(block.seal());
+        }
+    }
+
+    pub fn open_block(&self) -> Option<&OpenBlock> {
+        self.open.as_ref()
+    }
+
+    /// The sealed blocks, oldest first.
+    pub fn sealed_blocks(&self) -> &[SealedBlock] {
+        &self.sealed
+    }
+
+    /// Removes the `count` oldest sealed blocks, or all of them if there are
+    /// fewer. See `RetentionPolicy`.
+    pub fn remove_oldest(&mut self, count: usize) -> Drain<'_, SealedBlock> {
+        let count = count.min(self.sealed.len());
+        self.sealed.drain(..count)
+    }
+
+    /// The size of all blocks, including the open one. See
+    /// `SealedBlock::size_bytes`.
+    pub fn size_bytes(&self) -> usize {
+        self.sealed.iter().map(|block| block.size_bytes()).sum::<usize>()
+            + self.open.as_ref().map_or(0, |block| block.size_bytes())
+    }
+
+    pub fn last_timestamp(&self) -> Option<u64> {
+        match self.open {
+            Some(ref block) => Some(block.last_timestamp),
+            None => self.sealed.last().map(|block| block.last_timestamp),
+        }
+    }
+
+    /// Iterates over all samples, oldest first.
+    pub fn iter<'a>(&'a self) -> impl Iterator<Item = (u64, f64)> + 'a {
+        self.sealed.iter().flat_map(|block| block.iter())
+            .chain(self.open.iter().flat_map(|block| block.iter()))
+    }
+
+    /// The samples with timestamps in `start..end`, oldest first. Blocks
+    /// entirely outside of the range are skipped without being decoded.
+    pub fn range<'a>(&'a self, start: u64, end: u64) -> impl Iterator<Item = (u64, f64)> + 'a {
+        let sealed = self.sealed.iter()
+            .skip_while(move |block| block.last_timestamp < start)
+            .take_while(move |block| block.header_time < end)
+            .flat_map(move |block| block.range(start, end));
+        let open = self.open.iter()
+            .filter(move |block| block.last_timestamp >= start && block.header_time < end)
+            .flat_map(move |block| block.range(start, end));
+
+        sealed.chain(open)
+    }
+}
+
+impl Default for Series {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn aligned_blocks() {
+        let mut s = Series::new();
+        s.push(1496366523, 1.0).unwrap();
+        s.push(1496366583, 2.0).unwrap();
+
+        assert!(s.sealed_blocks().is_empty());
+        let open = s.open_block().unwrap();
+        assert_eq!(open.header_time(), 1496361600);
+        assert_eq!(open.count(), 2);
+        assert_eq!(open.last_timestamp(), 1496366583);
+    }
+
+    #[test]
+    fn roll_over() {
+        let mut s = Series::with_window(100);
+        let samples = [(10, 1.0), (99, 2.0), (100, 3.0), (150, 4.0), (420, 5.0)];
+        for &(timestamp, value) in samples.iter() {
+            s.push(timestamp, value).unwrap();
+        }
+
+        let headers: Vec<u64> = s.sealed_blocks().iter().map(|block| block.header_time()).collect();
+        assert_eq!(headers, vec![0, 100]);
+        assert_eq!(s.sealed_blocks()[0].iter().collect::<Vec<_>>(), vec![(10, 1.0), (99, 2.0)]);
+        assert_eq!(s.sealed_blocks()[1].count(), 2);
+        assert_eq!(s.open_block().unwrap().header_time(), 400);
+
+        assert_eq!(s.iter().collect::<Vec<_>>(), samples.to_vec());
+    }
+
+    #[test]
+    fn range() {
+        let mut s = Series::with_window(100);
+        let samples: Vec<(u64, f64)> = (0..50).map(|i| (i * 10, i as f64)).collect();
+        for &(timestamp, value) in samples.iter() {
+            s.push(timestamp, value).unwrap();
+        }
+        assert_eq!(s.sealed_blocks().len(), 4);
+
+        assert_eq!(s.range(0, 1000).collect::<Vec<_>>(), samples);
+        assert_eq!(s.range(95, 125).collect::<Vec<_>>(), vec![(100, 10.0), (110, 11.0), (120, 12.0)]);
+        assert_eq!(s.range(390, 420).collect::<Vec<_>>(), vec![(390, 39.0), (400, 40.0), (410, 41.0)]);
+        assert_eq!(s.range(455, 460).collect::<Vec<_>>(), vec![]);
+        assert_eq!(s.range(500, 600).collect::<Vec<_>>(), vec![]);
+        assert_eq!(s.sealed_blocks()[2].range(250, 270).collect::<Vec<_>>(), vec![(250, 25.0), (260, 26.0)]);
+    }
+
+    #[test]
+    fn seal() {
+        let mut s = Series::with_window(100);
+        s.push(10, 1.0).unwrap();
+        s.seal();
+        assert!(s.open_block().is_none());
+        assert_eq!(s.sealed_blocks().len(), 1);
+
+        // a new block is opened for the same window
+        s.push(20, 2.0).unwrap();
+        assert_eq!(s.open_block().unwrap().header_time(), 0);
+        assert_eq!(s.iter().collect::<Vec<_>>(), vec![(10, 1.0), (20, 2.0)]);
+    }
+
+    #[test]
+    fn remove_oldest() {
+        let mut s = Series::with_window(100);
+        for i in 0..50 {
+            s.push(i * 10, i as f64).unwrap();
+        }
+
+        let removed: Vec<u64> = s.remove_oldest(1).map(|block| block.header_time()).collect();
+        assert_eq!(removed, vec![0]);
+        assert_eq!(s.remove_oldest(10).count(), 3);
+        assert!(s.sealed_blocks().is_empty());
+        assert_eq!(s.iter().count(), 10);
+    }
+
+    #[test]
+    fn out_of_order() {
+        let mut s = Series::with_window(100);
+        s.push(150, 1.0).unwrap();
+        assert_eq!(s.push(90, 2.0), Err(EncodeError::OutOfOrder { timestamp: 90, previous: 150 }));
+        assert_eq!(s.push(140, 2.0), Err(EncodeError::OutOfOrder { timestamp: 140, previous: 150 }));
+        s.push(250, 3.0).unwrap();
+        assert_eq!(s.push(200, 4.0), Err(EncodeError::OutOfOrder { timestamp: 200, previous: 250 }));
+        assert_eq!(s.iter().collect::<Vec<_>>(), vec![(150, 1.0), (250, 3.0)]);
+    }
+
+    #[test]
+    fn milliseconds() {
+        let format = TimestampFormat { precision: TimestampPrecision::Milliseconds, ..Default::default() };
+        let mut s = Series::with_format(DEFAULT_WINDOW * format.precision.ticks_per_second(), format);
+        for i in 0..1_000 {
+            s.push(1496366523000 + i * 10_000, i as f64).unwrap();
+        }
+        let headers: Vec<u64> = s.sealed_blocks().iter().map(|block| block.header_time()).collect();
+        assert_eq!(headers, vec![1496361600000, 1496368800000]);
+        assert_eq!(s.iter().count(), 1_000);
+    }
+}
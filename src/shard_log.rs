@@ -0,0 +1,285 @@
+//! An append-only log of samples for crash recovery, like the shard log in
+//! the Gorilla paper. Each record is a series id, a timestamp and a value,
+//! stored big-endian in 20 bytes.
+//!
+//! Records are buffered in memory and the file is synced when the buffer is
+//! full, or on the first append after the loss window has passed. There is no
+//! background flush: if appends stop, buffered records stay in memory until
+//! the next append, an explicit `sync` or the log is dropped, so callers that
+//! need the loss window to hold when idle should call `sync` on a timer. A
+//! crash loses every record appended since the last sync.
+//!
+//! A record that was only partially written when the process died is ignored
+//! on replay, and cut off when the log is next opened so that later records
+//! stay aligned.
+//!
+//! Once every logged sample is in a checkpointed block, the log can be
+//! truncated.
+
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Write, BufReader};
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use series::Series;
+use timestamp_stream::TimestampFormat;
+
+const RECORD_SIZE: usize = 4 + 8 + 8;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct LogRecord {
+    pub id: u32,
+    pub timestamp: u64,
+    pub value: f64,
+}
+
+impl LogRecord {
+    fn to_bytes(self) -> [u8; RECORD_SIZE] {
+        let mut bytes = [0u8; RECORD_SIZE];
+        bytes[0..4].copy_from_slice(&self.id.to_be_bytes());
+        bytes[4..12].copy_from_slice(&self.timestamp.to_be_bytes());
+        bytes[12..20].copy_from_slice(&self.value.to_bits().to_be_bytes());
+        bytes
+    }
+
+    fn from_bytes(bytes: &[u8; RECORD_SIZE]) -> Self {
+        let mut id = [0u8; 4];
+        let mut timestamp = [0u8; 8];
+        let mut value = [0u8; 8];
+        id.copy_from_slice(&bytes[0..4]);
+        timestamp.copy_from_slice(&bytes[4..12]);
+        value.copy_from_slice(&bytes[12..20]);
+
+        LogRecord {
+            id: u32::from_be_bytes(id),
+            timestamp: u64::from_be_bytes(timestamp),
+            value: f64::from_bits(u64::from_be_bytes(value)),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct LogOptions {
+    /// Number of bytes buffered before the log is written and synced.
+    pub buffer_size: usize,
+    /// The first append this long after the last sync syncs the log.
+    pub loss_window: Duration,
+}
+
+impl Default for LogOptions {
+    /// A 64 KiB buffer and a one second loss window.
+    fn default() -> Self {
+        LogOptions {
+            buffer_size: 64 * 1024,
+            loss_window: Duration::from_secs(1),
+        }
+    }
+}
+
+pub struct ShardLog {
+    file: File,
+    buffer: Vec<u8>,
+    options: LogOptions,
+    last_sync: Instant,
+}
+
+impl ShardLog {
+    /// Opens the log at `path` for appending, creating it if needed. A
+    /// partial record left at the end by a crash is truncated.
+    pub fn open<P: AsRef<Path>>(path: P, options: LogOptions) -> io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+
+        let length = file.metadata()?.len();
+        let torn = length % RECORD_SIZE as u64;
+        if torn != 0 {
+            file.set_len(length - torn)?;
+            file.sync_all()?;
+        }
+
+        Ok(ShardLog {
+            file,
+            buffer: Vec::with_capacity(options.buffer_size),
+            options,
+            last_sync: Instant::now(),
+        })
+    }
+
+    pub fn append(&mut self, id: u32, timestamp: u64, value: f64) -> io::Result<()> {
+        self.buffer.extend_from_slice(&LogRecord { id, timestamp, value }.to_bytes());
+
+        if self.buffer.len() >= self.options.buffer_size || self.last_sync.elapsed() >= self.options.loss_window {
+            self.sync()?;
+        }
+
+        Ok(())
+    }
+
+    /// Writes the buffered records and syncs them to disk.
+    pub fn sync(&mut self) -> io::Result<()> {
+        self.file.write_all(&self.buffer)?;
+        self.buffer.clear();
+        self.file.sync_data()?;
+        self.last_sync = Instant::now();
+        Ok(())
+    }
+
+    /// Discards all records, including buffered ones. Call this once every
+    /// logged sample is in a checkpointed block.
+    pub fn truncate(&mut self) -> io::Result<()> {
+        self.buffer.clear();
+        self.file.set_len(0)?;
+        self.file.sync_all()?;
+        self.last_sync = Instant::now();
+        Ok(())
+    }
+}
+
+impl Drop for ShardLog {
+    fn drop(&mut self) {
+        let _ = self.sync();
+    }
+}
+
+/// Iterates over the records in a log, ignoring a partial record at the end.
+pub struct LogReader<R: Read> {
+    reader: R,
+}
+
+impl LogReader<BufReader<File>> {
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        Ok(LogReader::new(BufReader::new(File::open(path)?)))
+    }
+}
+
+impl<R> LogReader<R> where R: Read {
+    pub fn new(reader: R) -> Self {
+        LogReader { reader }
+    }
+}
+
+impl<R> Iterator for LogReader<R> where R: Read {
+    type Item = io::Result<LogRecord>;
+
+    fn next(&mut self) -> Option<io::Result<LogRecord>> {
+        let mut bytes = [0u8; RECORD_SIZE];
+        match self.reader.read_exact(&mut bytes) {
+            Ok(()) => Some(Ok(LogRecord::from_bytes(&bytes))),
+            Err(ref error) if error.kind() == io::ErrorKind::UnexpectedEof => None,
+            Err(error) => Some(Err(error)),
+        }
+    }
+}
+
+/// Replays the log at `path` into a fresh series per id, each created with
+/// `Series::with_format(window, format)`. Samples that can't be encoded are
+/// skipped, as they were rejected when first pushed.
+pub fn replay<P: AsRef<Path>>(path: P, window: u64, format: TimestampFormat) -> io::Result<HashMap<u32, Series>> {
+    let mut series = HashMap::new();
+
+    for record in LogReader::open(path)? {
+        let record = record?;
+        let _ = series.entry(record.id)
+            .or_insert_with(|| Series::with_format(window, format))
+            .push(record.timestamp, record.value);
+    }
+
+    Ok(series)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use series::DEFAULT_WINDOW;
+    use std::env;
+    use std::fs;
+    use std::path::PathBuf;
+    use std::process;
+
+    fn temp_path(name: &str) -> PathBuf {
+        let path = env::temp_dir().join(format!("gibbon-{}-{}", process::id(), name));
+        let _ = fs::remove_file(&path);
+        path
+    }
+
+    #[test]
+    fn append_and_replay() {
+        let path = temp_path("append_and_replay");
+        {
+            let mut log = ShardLog::open(&path, LogOptions::default()).unwrap();
+            log.append(1, 1496366523, 0.5).unwrap();
+            log.append(2, 1496366523, 7.0).unwrap();
+            log.append(1, 1496366583, 0.25).unwrap();
+        }
+
+        let series = replay(&path, DEFAULT_WINDOW, TimestampFormat::default()).unwrap();
+        assert_eq!(series.len(), 2);
+        assert_eq!(series[&1].iter().collect::<Vec<_>>(), vec![(1496366523, 0.5), (1496366583, 0.25)]);
+        assert_eq!(series[&2].iter().collect::<Vec<_>>(), vec![(1496366523, 7.0)]);
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn batched_sync() {
+        let path = temp_path("batched_sync");
+        let options = LogOptions { buffer_size: 2 * RECORD_SIZE, loss_window: Duration::from_secs(3600) };
+        let mut log = ShardLog::open(&path, options).unwrap();
+
+        log.append(1, 10, 1.0).unwrap();
+        assert_eq!(fs::metadata(&path).unwrap().len(), 0);
+        log.append(1, 20, 2.0).unwrap();
+        assert_eq!(fs::metadata(&path).unwrap().len(), 2 * RECORD_SIZE as u64);
+
+        // a zero loss window syncs every record
+        let options = LogOptions { buffer_size: 1024, loss_window: Duration::from_secs(0) };
+        let mut log = ShardLog::open(&path, options).unwrap();
+        log.append(1, 30, 3.0).unwrap();
+        assert_eq!(fs::metadata(&path).unwrap().len(), 3 * RECORD_SIZE as u64);
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn torn_record() {
+        let path = temp_path("torn_record");
+        {
+            let mut log = ShardLog::open(&path, LogOptions::default()).unwrap();
+            log.append(1, 10, 1.0).unwrap();
+            log.append(1, 20, 2.0).unwrap();
+        }
+        let length = fs::metadata(&path).unwrap().len();
+        OpenOptions::new().write(true).open(&path).unwrap().set_len(length - 3).unwrap();
+
+        let records: Vec<LogRecord> = LogReader::open(&path).unwrap().map(|record| record.unwrap()).collect();
+        assert_eq!(records, vec![LogRecord { id: 1, timestamp: 10, value: 1.0 }]);
+
+        // appending after the crash doesn't misalign the records
+        {
+            let mut log = ShardLog::open(&path, LogOptions::default()).unwrap();
+            log.append(1, 30, 3.0).unwrap();
+        }
+        assert_eq!(fs::metadata(&path).unwrap().len(), 2 * RECORD_SIZE as u64);
+        let series = replay(&path, DEFAULT_WINDOW, TimestampFormat::default()).unwrap();
+        assert_eq!(series[&1].iter().collect::<Vec<_>>(), vec![(10, 1.0), (30, 3.0)]);
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn truncate() {
+        let path = temp_path("truncate");
+        let mut log = ShardLog::open(&path, LogOptions::default()).unwrap();
+        log.append(1, 10, 1.0).unwrap();
+        log.sync().unwrap();
+        log.append(1, 20, 2.0).unwrap();
+        log.truncate().unwrap();
+        log.append(1, 30, 3.0).unwrap();
+        drop(log);
+
+        let series = replay(&path, DEFAULT_WINDOW, TimestampFormat::default()).unwrap();
+        assert_eq!(series[&1].iter().collect::<Vec<_>>(), vec![(30, 3.0)]);
+
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,114 @@
+//! This module provides a reader for bits in a borrowed byte slice, such as a
+//! memory mapped file or a network buffer. Bits are read most significant bit
+//! first, matching `VecWriter::to_bytes`, and the slice needs no alignment.
+
+pub use stream::{Reader, SeekableReader};
+
+pub struct SliceReader<'a> {
+    data: &'a [u8],
+    num_bits: usize,
+    position: usize,
+}
+
+impl<'a> SliceReader<'a> {
+    /// Reads the first `num_bits` bits of `data`. Any bits past the end of
+    /// `data` are ignored.
+    pub fn new(data: &'a [u8], num_bits: usize) -> Self {
+        SliceReader {
+            data,
+            num_bits: num_bits.min(data.len() * 8),
+            position: 0,
+        }
+    }
+}
+
+impl<'a> Reader for SliceReader<'a> {
+    fn read(&mut self, count: u8) -> Option<u64> {
+        let count = count as usize;
+        if self.num_bits - self.position < count {
+            return None;
+        }
+
+        let mut result = 0u64;
+        let mut remaining = count;
+        while remaining > 0 {
+            let byte = self.data[self.position / 8] as u64;
+            let available = 8 - self.position % 8;
+            let taken = available.min(remaining);
+
+            let bits = (byte >> (available - taken)) & ((1 << taken) - 1);
+            result = (result << taken) | bits;
+
+            self.position += taken;
+            remaining -= taken;
+        }
+
+        Some(result)
+    }
+
+    fn position(&self) -> usize {
+        self.position
+    }
+}
+
+impl<'a> SeekableReader for SliceReader<'a> {
+    fn seek(&mut self, position: usize) {
+        self.position = position.min(self.num_bits);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use vec_stream::VecWriter;
+    use time_and_value_stream::*;
+
+    #[test]
+    fn read_bytes() {
+        let data = [0b11010000, 0b01000000, 0b00000000, 0b00000001];
+        let mut r = SliceReader::new(&data, 32);
+        assert_eq!(r.read(4), Some(0b1101));
+        assert_eq!(r.read(4), Some(0b0000));
+        assert_eq!(r.read(1), Some(0b0));
+        assert_eq!(r.read(1), Some(0b1));
+        assert_eq!(r.read(21), Some(0));
+        assert_eq!(r.position(), 31);
+        assert_eq!(r.read(2), None);
+        assert_eq!(r.read(1), Some(1));
+        assert_eq!(r.read(1), None);
+    }
+
+    #[test]
+    fn read_64_unaligned() {
+        let data = [0xFF, 0x01, 0x23, 0x45, 0x67, 0x89, 0xAB, 0xCD, 0xEF, 0xFF];
+        let mut r = SliceReader::new(&data[1..], 100);
+        assert_eq!(r.read(64), Some(0x0123456789ABCDEF));
+        assert_eq!(r.read(8), Some(0xFF));
+        assert_eq!(r.read(1), None);
+
+        let mut r = SliceReader::new(&data, 80);
+        assert_eq!(r.read(4), Some(0xF));
+        assert_eq!(r.read(64), Some(0xF0123456789ABCDE));
+    }
+
+    #[test]
+    fn read_empty_slice() {
+        let mut r = SliceReader::new(&[], 0);
+        assert_eq!(r.read(1), None);
+    }
+
+    #[test]
+    fn time_and_value() {
+        let header_time = 10000;
+        let mut w = VecWriter::new();
+        let mut c = TimeAndValueStream::new(header_time);
+        let numbers = [(10005, 0.34f64), (10065, 0.35f64), (10124, 0.72f64), (10247, 0.42f64), (10365, 1.12f64)];
+        for &(timestamp, value) in numbers.iter() {
+            c.push(timestamp, value, &mut w);
+        }
+
+        let bytes = w.to_bytes();
+        let r = TimeAndValueIterator::new(SliceReader::new(&bytes, w.len()), header_time);
+        assert_eq!(r.collect::<Vec<_>>(), numbers.to_vec());
+    }
+}
@@ -1,9 +1,37 @@
+use error::DecodeError;
+
 pub trait Writer {
     /// write the `count` least significant bits of `bits`
     fn write(&mut self, bits: u64, count: u8);
+
+    /// the number of bits written so far, used for restart points. Required
+    /// since 0.2.0, which is a breaking change for writers implemented
+    /// outside this crate.
+    fn position(&self) -> usize;
 }
 
 pub trait Reader {
     fn read(&mut self, count: u8) -> Option<u64>;
+
+    /// the number of bits read so far, used for the offsets in
+    /// `DecodeError`s. Required since 0.2.0, which is a breaking change for
+    /// readers implemented outside this crate.
+    fn position(&self) -> usize;
+}
+
+/// A reader that can jump to any bit, such as `VecReader` or `SliceReader`.
+pub trait SeekableReader: Reader {
+    /// move to `position` bits from the start
+    fn seek(&mut self, position: usize);
+}
+
+/// A compressor for a stream of `f64`s, such as `DoubleStreamWriter` or
+/// `DoubleStreamLeadTrail`.
+pub trait ValueEncoder {
+    fn push(&mut self, number: f64, writer: &mut dyn Writer);
 }
 
+/// The decompressing counterpart of a `ValueEncoder`.
+pub trait ValueDecoder {
+    fn next(&mut self, reader: &mut dyn Reader) -> Result<Option<f64>, DecodeError>;
+}
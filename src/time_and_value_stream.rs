@@ -1,60 +1,238 @@
 //! This is a compound stream consisting of a timestamp followed by a double.
 //! This is how Gorilla compresses streams.
+//!
+//! The doubles are compressed with `DoubleStreamWriter` by default, but any
+//! `ValueEncoder`/`ValueDecoder` pair can be used instead.
 
 use timestamp_stream::*;
 use double_stream::*;
+use stream::SeekableReader;
+use restart_index::RestartIndex;
+use vec_stream::VecReader;
 
-pub struct TimeAndValueStream {
+pub struct TimeAndValueStream<E: ValueEncoder = DoubleStreamWriter> {
     timestamps: TimestampStreamWriter,
-    values: DoubleStreamWriter,
+    values: E,
 }
 
 impl TimeAndValueStream {
     pub fn new(header_time: u64) -> Self {
+        TimeAndValueStream::with_encoder(header_time, DoubleStreamWriter::new())
+    }
+}
+
+impl<E> TimeAndValueStream<E> where E: ValueEncoder {
+    pub fn with_encoder(header_time: u64, values: E) -> Self {
+        TimeAndValueStream::from_parts(TimestampStreamWriter::new(header_time), values)
+    }
+
+    pub fn from_parts(timestamps: TimestampStreamWriter, values: E) -> Self {
         TimeAndValueStream {
-            timestamps: TimestampStreamWriter::new(header_time),
-            values: DoubleStreamWriter::new(),
+            timestamps,
+            values,
         }
     }
 
-    pub fn push(&mut self, timestamp: u64, number: f64, writer: &mut Writer) {
+    pub fn timestamps(&self) -> &TimestampStreamWriter {
+        &self.timestamps
+    }
+
+    pub fn values(&self) -> &E {
+        &self.values
+    }
+
+    /// Panics if the timestamp can't be encoded. See `try_push`.
+    pub fn push(&mut self, timestamp: u64, number: f64, writer: &mut dyn Writer) {
         self.timestamps.push(timestamp, writer);
         self.values.push(number, writer);
     }
+
+    /// Encodes a sample, or returns an error without writing anything if the
+    /// timestamp can't be encoded. See `TimestampStreamWriter::try_push`.
+    pub fn try_push(&mut self, timestamp: u64, number: f64, writer: &mut dyn Writer) -> Result<(), EncodeError> {
+        self.timestamps.try_push(timestamp, writer)?;
+        self.values.push(number, writer);
+        Ok(())
+    }
 }
 
-pub struct TimeAndValueIterator<R: Reader> {
+/// Iterates over the timestamps and values in a stream. Iteration stops at the
+/// first decode error, which is then available from `error`.
+pub struct TimeAndValueIterator<R: Reader, D: ValueDecoder = DoubleStreamParser> {
     timestamp_parser: TimestampStreamParser,
-    value_parser: DoubleStreamParser,
+    value_parser: D,
     reader: R,
+    error: Option<DecodeError>,
 }
 
-impl<R> TimeAndValueIterator<R> where R: Reader{
+impl<R> TimeAndValueIterator<R> where R: Reader {
     pub fn new(reader: R, header_time: u64) -> Self {
+        TimeAndValueIterator::with_decoder(reader, header_time, DoubleStreamParser::new())
+    }
+}
+
+impl<R, D> TimeAndValueIterator<R, D> where R: Reader, D: ValueDecoder {
+    pub fn with_decoder(reader: R, header_time: u64, value_parser: D) -> Self {
+        TimeAndValueIterator::from_parts(reader, TimestampStreamParser::new(header_time), value_parser)
+    }
+
+    pub fn from_parts(reader: R, timestamp_parser: TimestampStreamParser, value_parser: D) -> Self {
         TimeAndValueIterator {
-            timestamp_parser: TimestampStreamParser::new(header_time),
-            value_parser: DoubleStreamParser::new(),
-            reader: reader,
+            timestamp_parser,
+            value_parser,
+            reader,
+            error: None,
+        }
+    }
+
+    /// Returns `Ok(None)` at the end of the stream, or an error if the stream
+    /// is truncated or malformed.
+    pub fn try_next(&mut self) -> Result<Option<(u64, f64)>, DecodeError> {
+        let timestamp = match self.timestamp_parser.next(&mut self.reader)? {
+            Some(timestamp) => timestamp,
+            None => return Ok(None),
+        };
+
+        let offset = self.reader.position();
+        match self.value_parser.next(&mut self.reader)? {
+            Some(value) => Ok(Some((timestamp, value))),
+            None => Err(DecodeError::TruncatedPayload { offset }),
+        }
+    }
+
+    pub fn error(&self) -> Option<&DecodeError> {
+        self.error.as_ref()
+    }
+
+    /// The samples with timestamps in `start..end`. Decoding stops at the
+    /// first timestamp at or after `end`.
+    pub fn range(self, start: u64, end: u64) -> RangeIterator<Self> {
+        RangeIterator::new(self, start, end)
+    }
+}
+
+impl<'a> TimeAndValueIterator<VecReader<'a>> {
+    /// Decodes samples into `timestamps` and `values` until either is full or
+    /// the stream ends, and returns how many were decoded. See
+    /// `TimestampStreamIterator::decode_into`.
+    pub fn decode_into(&mut self, timestamps: &mut [u64], values: &mut [f64]) -> usize {
+        if self.error.is_some() {
+            return 0;
+        }
+
+        let mut buffered = self.reader.buffered();
+        let mut count = 0;
+        for (timestamp_slot, value_slot) in timestamps.iter_mut().zip(values.iter_mut()) {
+            let timestamp = match self.timestamp_parser.next(&mut buffered) {
+                Ok(Some(timestamp)) => timestamp,
+                Ok(None) => break,
+                Err(error) => {
+                    self.error = Some(error);
+                    break;
+                }
+            };
+
+            let offset = buffered.position();
+            match self.value_parser.next(&mut buffered) {
+                Ok(Some(value)) => {
+                    *timestamp_slot = timestamp;
+                    *value_slot = value;
+                },
+                Ok(None) => {
+                    self.error = Some(DecodeError::TruncatedPayload { offset });
+                    break;
+                },
+                Err(error) => {
+                    self.error = Some(error);
+                    break;
+                }
+            }
+            count += 1;
+        }
+
+        self.reader.seek(buffered.position());
+        count
+    }
+}
+
+impl<R> TimeAndValueIterator<R> where R: SeekableReader {
+    /// Skips ahead to the last restart point in `index` before `timestamp`,
+    /// unless the iterator is already past it. Samples between the restart
+    /// point and `timestamp` are still returned. The index must have been
+    /// written with this stream.
+    pub fn seek(&mut self, index: &RestartIndex, timestamp: u64) {
+        if let Some(point) = index.restart_point(timestamp) {
+            if point.bit_offset > self.reader.position() {
+                self.reader.seek(point.bit_offset);
+                self.timestamp_parser.set_state(point.timestamp_state());
+                self.value_parser.set_state(point.value_state());
+            }
         }
     }
 }
 
-impl<R> Iterator for TimeAndValueIterator<R> where R: Reader {
+impl<R, D> Iterator for TimeAndValueIterator<R, D> where R: Reader, D: ValueDecoder {
     type Item = (u64, f64);
 
     fn next(&mut self) -> Option<(u64, f64)> {
-        // unwrap second result with the assumption that the stream is welformed and we don't get
-        // access partial access to it
-        self.timestamp_parser.next(&mut self.reader)
-            .and_then(|timestamp| Some((timestamp, self.value_parser.next(&mut self.reader).unwrap())))
+        if self.error.is_some() {
+            return None;
+        }
+
+        self.try_next().unwrap_or_else(|error| {
+            self.error = Some(error);
+            None
+        })
+    }
+}
+
+/// The samples of an iterator with timestamps in `start..end`. Timestamps are
+/// expected to be in order, so iteration ends at the first one at or after
+/// `end` without reading any further.
+pub struct RangeIterator<I> {
+    inner: I,
+    start: u64,
+    end: u64,
+    done: bool,
+}
+
+impl<I> RangeIterator<I> where I: Iterator<Item = (u64, f64)> {
+    pub fn new(inner: I, start: u64, end: u64) -> Self {
+        RangeIterator {
+            inner,
+            start,
+            end,
+            done: start >= end,
+        }
+    }
+
+    pub fn into_inner(self) -> I {
+        self.inner
+    }
+}
+
+impl<I> Iterator for RangeIterator<I> where I: Iterator<Item = (u64, f64)> {
+    type Item = (u64, f64);
+
+    fn next(&mut self) -> Option<(u64, f64)> {
+        while !self.done {
+            match self.inner.next() {
+                Some((timestamp, _)) if timestamp < self.start => {},
+                Some((timestamp, value)) if timestamp < self.end => return Some((timestamp, value)),
+                _ => self.done = true,
+            }
+        }
+
+        None
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use vec_stream::{VecWriter, VecReader};
+    use vec_stream::{VecWriter, VecReader, BufferedVecReader};
     use bit_string_stream::*;
+    use double_stream_lead_trail::*;
 
     #[test]
     fn all_zeros_int() {
@@ -143,12 +321,13 @@ mod tests {
         let mut w = VecWriter::new();
         let mut c = TimeAndValueStream::new(header_time);
 
-        let mut numbers = Vec::new();
-        numbers.push((10005, 0.34f64));
-        numbers.push((10065, 0.35f64));
-        numbers.push((10124, 0.72f64));
-        numbers.push((10247, 0.42f64));
-        numbers.push((10365, 1.12f64));
+        let numbers = [
+            (10005, 0.34f64),
+            (10065, 0.35f64),
+            (10124, 0.72f64),
+            (10247, 0.42f64),
+            (10365, 1.12f64),
+        ];
 
         for &(timestamp, value) in numbers.iter() {
             c.push(timestamp, value, &mut w);
@@ -160,4 +339,301 @@ mod tests {
             assert_eq!(*from_vector, from_stream);
         }
     }
+
+    #[test]
+    fn time_and_value_lead_trail () {
+        let header_time = 10000;
+        let mut w = VecWriter::new();
+        let mut c = TimeAndValueStream::with_encoder(header_time, DoubleStreamLeadTrail::new());
+
+        let numbers = [
+            (10005, 0.34f64),
+            (10065, 0.35f64),
+            (10124, 0.72f64),
+            (10247, 0.42f64),
+            (10365, 1.12f64),
+        ];
+
+        for &(timestamp, value) in numbers.iter() {
+            c.push(timestamp, value, &mut w);
+        }
+
+        let r = TimeAndValueIterator::with_decoder(VecReader::new(&w.bit_vector, w.used_bits_last_elm), header_time, DoubleStreamLeadTrailParser::new());
+
+        let read: Vec<(u64, f64)> = r.collect();
+        assert_eq!(&read[..], &numbers[..]);
+    }
+
+    #[test]
+    fn truncated_timestamp() {
+        let mut r = StringReader::new("0000000000000111".to_string());
+        let mut p = TimestampStreamParser::new(0);
+        assert_eq!(p.next(&mut r), Ok(Some(1)));
+        assert_eq!(p.next(&mut r), Err(DecodeError::TruncatedControl { offset: 16 }));
+
+        let mut r = StringReader::new("0000000000000111100000".to_string());
+        let mut p = TimestampStreamParser::new(0);
+        assert_eq!(p.next(&mut r), Ok(Some(1)));
+        assert_eq!(p.next(&mut r), Err(DecodeError::TruncatedPayload { offset: 18 }));
+    }
+
+    #[test]
+    fn truncated_time_and_value() {
+        let mut w = StringWriter::new();
+        let mut c = TimeAndValueStream::new(0);
+        c.push(1, 0.5, &mut w);
+        c.push(2, 0.5, &mut w);
+
+        // cut off the value of the second sample
+        let length = w.string.len();
+        let mut r = TimeAndValueIterator::new(StringReader::new(w.string[..length - 1].to_string()), 0);
+        assert_eq!(r.try_next(), Ok(Some((1, 0.5))));
+        assert_eq!(r.try_next(), Err(DecodeError::TruncatedPayload { offset: 14 + 64 + 1 }));
+
+        let mut r = TimeAndValueIterator::new(StringReader::new(w.string[..length - 1].to_string()), 0);
+        assert_eq!(r.next(), Some((1, 0.5)));
+        assert_eq!(r.next(), None);
+        assert!(r.error().is_some());
+    }
+
+    #[test]
+    fn range() {
+        let mut w = VecWriter::new();
+        let mut c = TimeAndValueStream::new(0);
+        for i in 0..10 {
+            c.push(i * 10, i as f64, &mut w);
+        }
+        let range = |start, end| {
+            TimeAndValueIterator::new(VecReader::new(&w.bit_vector, w.used_bits_last_elm), 0).range(start, end).collect::<Vec<_>>()
+        };
+
+        assert_eq!(range(20, 50), vec![(20, 2.0), (30, 3.0), (40, 4.0)]);
+        assert_eq!(range(15, 21), vec![(20, 2.0)]);
+        assert_eq!(range(0, 1), vec![(0, 0.0)]);
+        assert_eq!(range(85, 1000), vec![(90, 9.0)]);
+        assert_eq!(range(30, 30), vec![]);
+        assert_eq!(range(100, 200), vec![]);
+    }
+
+    #[test]
+    fn range_stops_early() {
+        let mut w = StringWriter::new();
+        let mut c = TimeAndValueStream::new(0);
+        c.push(1, 0.5, &mut w);
+        c.push(2, 0.5, &mut w);
+        c.push(3, 0.5, &mut w);
+        c.push(4, 0.5, &mut w);
+
+        // the last sample is corrupt, but is never read
+        let length = w.string.len();
+        let mut r = TimeAndValueIterator::new(StringReader::new(w.string[..length - 1].to_string()), 0).range(0, 3);
+        assert_eq!(r.next(), Some((1, 0.5)));
+        assert_eq!(r.next(), Some((2, 0.5)));
+        assert_eq!(r.next(), None);
+        assert!(r.into_inner().error().is_none());
+    }
+
+    #[test]
+    fn try_push_errors() {
+        let mut w = StringWriter::new();
+        let mut c = TimestampStreamWriter::new(100);
+        assert_eq!(c.try_push(99, &mut w), Err(EncodeError::BeforeHeader { timestamp: 99, header_time: 100 }));
+        assert_eq!(c.try_push(100 + (1 << 14), &mut w), Err(EncodeError::FirstDeltaTooLarge { delta: 1 << 14, max: (1 << 14) - 1 }));
+        assert_eq!(w.string, "");
+
+        c.try_push(110, &mut w).unwrap();
+        c.try_push(120, &mut w).unwrap();
+        let written = w.string.clone();
+        assert_eq!(c.try_push(119, &mut w), Err(EncodeError::OutOfOrder { timestamp: 119, previous: 120 }));
+        assert_eq!(c.try_push(120 + (1 << 32), &mut w), Err(EncodeError::DeltaOfDeltasTooLarge { delta_of_deltas: (1 << 32) - 10 }));
+        assert_eq!(c.try_push(120 + (1 << 63), &mut w), Err(EncodeError::DeltaTooLarge { delta: 1 << 63 }));
+        assert_eq!(w.string, written);
+
+        // the writer carries on as if the failing timestamps were never pushed
+        c.try_push(130, &mut w).unwrap();
+
+        let r = TimestampStreamIterator::new(StringReader::new(w.string), 100);
+        assert_eq!(r.collect::<Vec<_>>(), vec![110, 120, 130]);
+    }
+
+    #[test]
+    fn try_push_time_and_value() {
+        let mut w = VecWriter::new();
+        let mut c = TimeAndValueStream::new(0);
+        c.try_push(10, 1.0, &mut w).unwrap();
+        assert!(c.try_push(5, 2.0, &mut w).is_err());
+        c.try_push(20, 3.0, &mut w).unwrap();
+
+        let r = TimeAndValueIterator::new(VecReader::new(&w.bit_vector, w.used_bits_last_elm), 0);
+        assert_eq!(r.collect::<Vec<_>>(), vec![(10, 1.0), (20, 3.0)]);
+    }
+
+    #[test]
+    fn negative_escape() {
+        let mut w = StringWriter::new();
+        let mut c = TimestampStreamWriter::new(0);
+        c.push(   0, &mut w);
+        c.push(5000, &mut w); // delta 5000, dod =  5000
+        c.push(7000, &mut w); // delta 2000, dod = -3000
+        //                          1111[dod = -3000 in 32 bits          ]
+        assert!(w.string.ends_with("111111111111111111111111010001001000"));
+
+        let r = TimestampStreamIterator::new(StringReader::new(w.string), 0);
+        assert_eq!(r.collect::<Vec<_>>(), vec![0, 5000, 7000]);
+    }
+
+    #[test]
+    fn version_2_escape() {
+        let century = 100 * 365 * 24 * 3600;
+        let timestamps = [10, 20, 20 + century, 30 + century, 40 + century, 41 + century, 1 << 62];
+        let format = TimestampFormat { version: TimestampVersion::V2, ..Default::default() };
+
+        let mut w = VecWriter::new();
+        let mut c = TimestampStreamWriter::new(0);
+        c.push(10, &mut w);
+        c.push(20, &mut w);
+        assert_eq!(c.try_push(20 + century, &mut w), Err(EncodeError::DeltaOfDeltasTooLarge { delta_of_deltas: (century - 10) as i64 }));
+
+        let mut w = VecWriter::new();
+        let mut c = TimeAndValueStream::from_parts(TimestampStreamWriter::with_format(0, format), DoubleStreamWriter::new());
+        for &timestamp in timestamps.iter() {
+            c.push(timestamp, timestamp as f64, &mut w);
+        }
+
+        let r = TimeAndValueIterator::from_parts(
+            VecReader::new(&w.bit_vector, w.used_bits_last_elm),
+            TimestampStreamParser::with_format(0, format),
+            DoubleStreamParser::new());
+        let read: Vec<u64> = r.map(|(timestamp, _value)| timestamp).collect();
+        assert_eq!(&read[..], &timestamps[..]);
+    }
+
+    #[test]
+    fn milliseconds() {
+        let format = TimestampFormat { precision: TimestampPrecision::Milliseconds, ..Default::default() };
+        let header_time = 1496361600000;
+        // two hours in, with 10 second intervals and some jitter
+        let timestamps = [header_time + 7_199_999, header_time + 7_210_003, header_time + 7_219_998, header_time + 7_229_998];
+
+        let mut w = StringWriter::new();
+        let mut c = TimestampStreamWriter::with_format(header_time, format);
+        c.push(timestamps[0], &mut w); assert_eq!(w.string.len(), 24);
+        c.push(timestamps[1], &mut w); assert_eq!(w.string.len(), 24 + 4 + 32);  // dod = -7189995
+        c.push(timestamps[2], &mut w); assert_eq!(w.string.len(), 24 + 4 + 32 + 2 + 17); // dod = -9
+        c.push(timestamps[3], &mut w); assert_eq!(w.string.len(), 24 + 4 + 32 + 2 + 17 + 2 + 17); // dod = 5
+
+        let r = TimestampStreamIterator::with_format(StringReader::new(w.string), header_time, format);
+        assert_eq!(r.collect::<Vec<_>>(), timestamps.to_vec());
+    }
+
+    #[test]
+    fn all_precisions() {
+        let precisions = [TimestampPrecision::Seconds, TimestampPrecision::Milliseconds,
+                          TimestampPrecision::Microseconds, TimestampPrecision::Nanoseconds];
+        let versions = [TimestampVersion::V1, TimestampVersion::V2];
+
+        for &precision in precisions.iter() {
+            for &version in versions.iter() {
+                let format = TimestampFormat { version, precision, table: None };
+                let interval = 10 * precision.ticks_per_second();
+                let jitter = precision.ticks_per_second() / 10 + 1;
+
+                let mut timestamps = Vec::new();
+                let mut w = VecWriter::new();
+                let mut c = TimestampStreamWriter::with_format(0, format);
+                for i in 0..1_000u64 {
+                    let timestamp = (i + 1) * interval + (i * 7919) % jitter;
+                    c.push(timestamp, &mut w);
+                    timestamps.push(timestamp);
+                }
+
+                let r = TimestampStreamIterator::with_format(VecReader::new(&w.bit_vector, w.used_bits_last_elm), 0, format);
+                assert_eq!(r.collect::<Vec<_>>(), timestamps);
+            }
+        }
+    }
+
+    #[test]
+    fn custom_table() {
+        // 10 second intervals with up to a second of jitter
+        let timestamps: Vec<u64> = (0..1_000u64).map(|i| 10 + i * 10 + (i * 7919) % 2).collect();
+        let table = DodTable::from_sample(&timestamps, TimestampFormat::default().escape_bits());
+        let format = TimestampFormat { table: Some(table), ..Default::default() };
+
+        let mut default_writer = VecWriter::new();
+        let mut c = TimestampStreamWriter::new(0);
+        for &timestamp in timestamps.iter() {
+            c.push(timestamp, &mut default_writer);
+        }
+
+        let mut w = VecWriter::new();
+        let mut c = TimestampStreamWriter::with_format(0, format);
+        for &timestamp in timestamps.iter() {
+            c.push(timestamp, &mut w);
+        }
+        assert!(w.len() < default_writer.len());
+
+        let r = TimestampStreamIterator::with_format(VecReader::new(&w.bit_vector, w.used_bits_last_elm), 0, format);
+        assert_eq!(r.collect::<Vec<_>>(), timestamps);
+    }
+
+    #[test]
+    fn decode_into() {
+        let header_time = 1496361600;
+        let samples: Vec<(u64, f64)> = (0..1_000u64).map(|i| (header_time + i * 10 + (i * 7919) % 3, (i % 37) as f64 * 0.25)).collect();
+        let mut w = VecWriter::new();
+        let mut c = TimeAndValueStream::new(header_time);
+        for &(timestamp, value) in samples.iter() {
+            c.push(timestamp, value, &mut w);
+        }
+
+        // decode in uneven runs, mixed with `next`
+        let mut r = TimeAndValueIterator::new(VecReader::new(&w.bit_vector, w.used_bits_last_elm), header_time);
+        let mut timestamps = [0u64; 300];
+        let mut values = [0f64; 250];
+        let mut read = vec![r.next().unwrap()];
+        loop {
+            let count = r.decode_into(&mut timestamps, &mut values);
+            read.extend(timestamps[..count].iter().cloned().zip(values[..count].iter().cloned()));
+            if count < values.len() {
+                break;
+            }
+        }
+        assert_eq!(read, samples);
+        assert_eq!(r.next(), None);
+        assert_eq!(r.error(), None);
+
+        let mut r = TimestampStreamIterator::new(VecReader::new(&w.bit_vector, w.used_bits_last_elm), header_time);
+        assert_eq!(r.decode_into(&mut timestamps[..1]), 1);
+        assert_eq!(timestamps[0], header_time);
+
+        let mut timestamp_writer = VecWriter::new();
+        let mut c = TimestampStreamWriter::new(header_time);
+        for &(timestamp, _) in samples.iter() {
+            c.push(timestamp, &mut timestamp_writer);
+        }
+        let mut r = BufferedVecReader::new(&timestamp_writer.bit_vector, timestamp_writer.used_bits_last_elm);
+        let mut p = TimestampStreamParser::new(header_time);
+        let mut read = vec![0u64; samples.len() + 1];
+        assert_eq!(p.decode_into(&mut r, &mut read[..400]), Ok(400));
+        assert_eq!(p.decode_into(&mut r, &mut read[400..]), Ok(600));
+        assert_eq!(read[..1000].to_vec(), samples.iter().map(|&(timestamp, _)| timestamp).collect::<Vec<_>>());
+        assert_eq!(p.decode_into(&mut r, &mut read), Ok(0));
+    }
+
+    #[test]
+    fn decode_into_truncated() {
+        let mut w = VecWriter::new();
+        let mut c = TimeAndValueStream::new(0);
+        c.push(10, 1.0, &mut w);
+        c.push(20, 2.0, &mut w);
+
+        let mut r = TimeAndValueIterator::new(VecReader::new(&w.bit_vector, w.used_bits_last_elm - 1), 0);
+        let mut timestamps = [0u64; 4];
+        let mut values = [0f64; 4];
+        assert_eq!(r.decode_into(&mut timestamps, &mut values), 1);
+        assert_eq!((timestamps[0], values[0]), (10, 1.0));
+        assert!(r.error().is_some());
+        assert_eq!(r.decode_into(&mut timestamps, &mut values), 0);
+    }
 }
@@ -1,10 +1,141 @@
 //! A timestamp stream contains integers and compresses well when they occur at
-//! regular intervals. They are also tuned to work well for seconds. While for
-//! example milliseconds would also work, it would compress poorly if not at an
-//! exact interval.
+//! regular intervals. By default they are tuned to work well for seconds. For
+//! other resolutions, a `TimestampPrecision` widens the delta of delta buckets
+//! and the first delta accordingly. The buckets can also be set explicitly
+//! with a `DodTable`.
+//!
+//! Delta of deltas that don't fit in any of the buckets are stored in 32 bits,
+//! unless the stream is written with `TimestampVersion::V2`, which stores them
+//! in 64 bits.
 
 pub use stream::{Writer, Reader};
+pub use error::{DecodeError, EncodeError};
+use error::{read_control, read_payload};
+pub use dod_table::DodTable;
+use dod_table::bias;
+use vec_stream::VecReader;
+use stream::SeekableReader;
 
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum TimestampVersion {
+    /// The original encoding, where delta of deltas outside of the buckets are
+    /// stored in 32 bits and must fit in an `i32`.
+    ///
+    /// Before 0.2.0, the 32 bits were read back unsigned, and the writer
+    /// corrupted the stream for negative ones. Streams written by 0.1 with an
+    /// escaped delta of deltas of 2^31 or more, a gap of over 68 years in
+    /// seconds, decode differently now.
+    #[default]
+    V1,
+    /// Delta of deltas outside of the buckets are stored in 64 bits, so any
+    /// `i64` can be encoded.
+    V2,
+}
+
+impl TimestampVersion {
+    pub fn id(&self) -> u8 {
+        match *self {
+            TimestampVersion::V1 => 1,
+            TimestampVersion::V2 => 2,
+        }
+    }
+
+    pub fn from_id(id: u8) -> Option<Self> {
+        match id {
+            1 => Some(TimestampVersion::V1),
+            2 => Some(TimestampVersion::V2),
+            _ => None,
+        }
+    }
+}
+
+/// The unit of the timestamps in a stream. With `TimestampVersion::V1`, delta of
+/// deltas must still fit in 32 bits, which is only about two seconds in
+/// nanoseconds, so finer precisions are best combined with `V2`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum TimestampPrecision {
+    #[default]
+    Seconds,
+    Milliseconds,
+    Microseconds,
+    Nanoseconds,
+}
+
+impl TimestampPrecision {
+    pub fn id(&self) -> u8 {
+        match *self {
+            TimestampPrecision::Seconds => 0,
+            TimestampPrecision::Milliseconds => 1,
+            TimestampPrecision::Microseconds => 2,
+            TimestampPrecision::Nanoseconds => 3,
+        }
+    }
+
+    pub fn from_id(id: u8) -> Option<Self> {
+        match id {
+            0 => Some(TimestampPrecision::Seconds),
+            1 => Some(TimestampPrecision::Milliseconds),
+            2 => Some(TimestampPrecision::Microseconds),
+            3 => Some(TimestampPrecision::Nanoseconds),
+            _ => None,
+        }
+    }
+
+    pub fn ticks_per_second(&self) -> u64 {
+        match *self {
+            TimestampPrecision::Seconds => 1,
+            TimestampPrecision::Milliseconds => 1_000,
+            TimestampPrecision::Microseconds => 1_000_000,
+            TimestampPrecision::Nanoseconds => 1_000_000_000,
+        }
+    }
+
+    /// The number of bits added to the widths tuned for seconds, which is
+    /// log2 of `ticks_per_second` rounded up.
+    pub fn extra_bits(&self) -> u8 {
+        match *self {
+            TimestampPrecision::Seconds => 0,
+            TimestampPrecision::Milliseconds => 10,
+            TimestampPrecision::Microseconds => 20,
+            TimestampPrecision::Nanoseconds => 30,
+        }
+    }
+}
+
+/// Header flags for a timestamp stream. They are not stored in the stream
+/// itself, so the parser must be given the same format as the writer. Block
+/// files store them in their header, see `block_file`, and
+/// `BlockFile::check_format` reports a block written in another format.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct TimestampFormat {
+    pub version: TimestampVersion,
+    pub precision: TimestampPrecision,
+    /// The delta of delta buckets, or `None` for `DodTable::for_precision`.
+    pub table: Option<DodTable>,
+}
+
+impl TimestampFormat {
+    /// The number of bits used for the delta between the header time and the
+    /// first timestamp. Enough to store more than four hours.
+    pub fn first_delta_bits(&self) -> u8 {
+        14 + self.precision.extra_bits()
+    }
+
+    pub fn table(&self) -> DodTable {
+        self.table.unwrap_or_else(|| DodTable::for_precision(self.precision))
+    }
+
+    /// The number of bits used for a delta of deltas that doesn't fit in any
+    /// of the buckets.
+    pub fn escape_bits(&self) -> u8 {
+        match self.version {
+            TimestampVersion::V1 => 32,
+            TimestampVersion::V2 => 64,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum TimestampStreamState {
     Initial {
         header_time: u64 // aligned to a two hour window
@@ -16,44 +147,84 @@ pub enum TimestampStreamState {
 }
 
 pub struct TimestampStreamWriter {
-    state: TimestampStreamState
+    state: TimestampStreamState,
+    format: TimestampFormat,
 }
 
 impl TimestampStreamWriter {
     pub fn new(header_time: u64) -> Self {
+        TimestampStreamWriter::with_format(header_time, TimestampFormat::default())
+    }
+
+    pub fn with_format(header_time: u64, format: TimestampFormat) -> Self {
         TimestampStreamWriter {
-            state: TimestampStreamState::Initial { header_time: header_time }
+            state: TimestampStreamState::Initial { header_time },
+            format,
         }
     }
 
-    pub fn push(&mut self, number: u64, writer: &mut Writer) {
+    /// The state after the last pushed timestamp, for resuming decoding there.
+    /// See `RestartIndex`.
+    pub fn state(&self) -> TimestampStreamState {
+        self.state
+    }
+
+    /// Panics if the timestamp can't be encoded. See `try_push`.
+    pub fn push(&mut self, number: u64, writer: &mut dyn Writer) {
+        if let Err(error) = self.try_push(number, writer) {
+            panic!("{}", error);
+        }
+    }
+
+    /// Encodes `number`, or returns an error without writing anything if it is
+    /// before the header time or the previous timestamp, or too far after them.
+    pub fn try_push(&mut self, number: u64, writer: &mut dyn Writer) -> Result<(), EncodeError> {
         let delta = match self.state {
             TimestampStreamState::Initial { header_time } => {
-                assert!(number >= header_time); // header time should be rounded down
+                if number < header_time { // header time should be rounded down
+                    return Err(EncodeError::BeforeHeader { timestamp: number, header_time });
+                }
                 let delta = number - header_time;
-                assert!(delta <= (1 << 14)); // enough to store more than four hours in seconds
-                writer.write(delta, 14);
+                let first_delta_bits = self.format.first_delta_bits();
+                let max = (1 << first_delta_bits) - 1;
+                if delta > max {
+                    return Err(EncodeError::FirstDeltaTooLarge { delta, max });
+                }
+                writer.write(delta, first_delta_bits);
 
                 delta as i64
             },
             TimestampStreamState::Following { value: prev_value, delta: prev_delta } => {
-                let delta = (number - prev_value) as i64;
+                if number < prev_value {
+                    return Err(EncodeError::OutOfOrder { timestamp: number, previous: prev_value });
+                }
+                let delta = number - prev_value;
+                if delta > i64::MAX as u64 {
+                    return Err(EncodeError::DeltaTooLarge { delta });
+                }
+                let delta = delta as i64;
                 let delta_of_deltas = delta - prev_delta;
+                let fits_escape = match self.format.version {
+                    TimestampVersion::V1 => delta_of_deltas >= i32::MIN as i64 && delta_of_deltas <= i32::MAX as i64,
+                    TimestampVersion::V2 => true,
+                };
+                if !fits_escape {
+                    return Err(EncodeError::DeltaOfDeltasTooLarge { delta_of_deltas });
+                }
+
+                let table = self.format.table();
 
                 if delta_of_deltas == 0 {
                     writer.write(0, 1);
-                } else if delta_of_deltas >= -63 && delta_of_deltas <= 64 {
-                    writer.write(0b10, 2);
-                    writer.write((delta_of_deltas + 63) as u64, 7);
-                } else if delta_of_deltas >= -255 && delta_of_deltas <= 256 {
-                    writer.write(0b110, 3);
-                    writer.write((delta_of_deltas + 255) as u64, 9);
-                } else if delta_of_deltas >= -2047 && delta_of_deltas <= 2048 {
-                    writer.write(0b1110, 4);
-                    writer.write((delta_of_deltas + 2047) as u64, 12);
+                } else if let Some(bucket) = table.bucket_for(delta_of_deltas) {
+                    // 10, 110 or 1110
+                    let bits = table.bucket_bits()[bucket];
+                    writer.write((1 << (bucket + 2)) - 2, bucket as u8 + 2);
+                    writer.write((delta_of_deltas + bias(bits)) as u64, bits);
                 } else {
+                    let escape_bits = self.format.escape_bits();
                     writer.write(0b1111, 4);
-                    writer.write(delta_of_deltas as u64, 32);
+                    writer.write(delta_of_deltas as u64 & (!0 >> (64 - escape_bits)), escape_bits);
                 }
 
                 delta
@@ -62,84 +233,175 @@ impl TimestampStreamWriter {
 
         self.state = TimestampStreamState::Following {
             value: number,
-            delta: delta
+            delta
         };
+
+        Ok(())
     }
 }
 
 pub struct TimestampStreamParser {
     state: TimestampStreamState,
+    format: TimestampFormat,
 }
 
 impl TimestampStreamParser {
     pub fn new(header_time: u64) -> Self {
+        TimestampStreamParser::with_format(header_time, TimestampFormat::default())
+    }
+
+    pub fn with_format(header_time: u64, format: TimestampFormat) -> Self {
         TimestampStreamParser {
-            state: TimestampStreamState::Initial { header_time: header_time }
+            state: TimestampStreamState::Initial { header_time },
+            format,
         }
     }
 
-    pub fn next(&mut self, reader: &mut Reader) -> Option<u64> {
-        let values = match self.state {
+    pub fn state(&self) -> TimestampStreamState {
+        self.state
+    }
+
+    /// Continues decoding from `state`, which the reader must be positioned
+    /// after.
+    pub fn set_state(&mut self, state: TimestampStreamState) {
+        self.state = state;
+    }
+
+    /// Returns `Ok(None)` at the end of the stream, or an error if the stream
+    /// ends in the middle of a timestamp.
+    pub fn next<R: Reader + ?Sized>(&mut self, reader: &mut R) -> Result<Option<u64>, DecodeError> {
+        let (value, delta) = match self.state {
             TimestampStreamState::Initial { header_time } => {
-                reader.read(14).and_then(|delta| Some((header_time + delta, delta as i64)))
+                match reader.read(self.format.first_delta_bits()) {
+                    Some(delta) => (header_time.wrapping_add(delta), delta as i64),
+                    None => return Ok(None),
+                }
             }
             TimestampStreamState::Following { value, delta } => {
                 match reader.read(1) {
-                    Some(0) => Some((value.wrapping_add(delta as u64), delta)),
-                    Some(1) => {
-                        // unwrapping reads from now on, on the assumption that the stream is
-                        // well-formed
-
-                        let (num_bits, bias) = if reader.read(1).unwrap() == 0 { // 10
-                            (7, 63)
-                        } else if reader.read(1).unwrap() == 0 { // 110
-                            (9, 255)
-                        } else if reader.read(1).unwrap() == 0 { // 1110
-                            (12, 2047)
-                        } else { // 1111
-                            (32, 0)
-                        };
+                    None => return Ok(None),
+                    Some(0) => (value.wrapping_add(delta as u64), delta),
+                    Some(_) => {
+                        // count the ones following the first one: 10, 110, 1110 or 1111
+                        let mut bucket = 0;
+                        while bucket < 3 && read_control(reader, 1)? == 1 {
+                            bucket += 1;
+                        }
 
-                        let delta_of_deltas = reader.read(num_bits).unwrap() as i64 - bias;
+                        let delta_of_deltas = if bucket < 3 {
+                            let bits = self.format.table().bucket_bits()[bucket];
+                            read_payload(reader, bits)? as i64 - bias(bits)
+                        } else {
+                            let escape_bits = self.format.escape_bits();
+                            let bits = read_payload(reader, escape_bits)?;
+                            // sign extend
+                            ((bits << (64 - escape_bits)) as i64) >> (64 - escape_bits)
+                        };
 
-                        let new_delta = delta + delta_of_deltas;
+                        let new_delta = delta.wrapping_add(delta_of_deltas);
                         let new_value = value.wrapping_add(new_delta as u64);
-                        Some((new_value, new_delta))
+                        (new_value, new_delta)
                     }
-                    None => None,
-                    _ => panic!("Binary read should not be able to return anything but 0 or 1")
                 }
             }
         };
 
-        if let Some((value, delta)) = values {
-            self.state = TimestampStreamState::Following { value: value, delta: delta };
-            Some(value)
-        } else {
-            None
+        self.state = TimestampStreamState::Following { value, delta };
+        Ok(Some(value))
+    }
+
+    /// Decodes timestamps into `out` until it is full or the stream ends, and
+    /// returns how many were decoded. Reading through a `BufferedVecReader`
+    /// makes this faster than calling `next` repeatedly.
+    ///
+    /// A malformed timestamp is returned as an error by the call that starts
+    /// at it, while a call that decodes some timestamps before it returns
+    /// their count. Either way, `reader` is left at the start of the
+    /// malformed timestamp.
+    pub fn decode_into<R: SeekableReader + ?Sized>(&mut self, reader: &mut R, out: &mut [u64]) -> Result<usize, DecodeError> {
+        for (count, slot) in out.iter_mut().enumerate() {
+            let position = reader.position();
+            match self.next(reader) {
+                Ok(Some(timestamp)) => *slot = timestamp,
+                Ok(None) => return Ok(count),
+                Err(error) => {
+                    reader.seek(position);
+                    return if count == 0 { Err(error) } else { Ok(count) };
+                }
+            }
         }
+        Ok(out.len())
     }
 }
 
+/// Iterates over the timestamps in a stream. Iteration stops at the first
+/// decode error, which is then available from `error`.
 pub struct TimestampStreamIterator<R> where R: Reader {
     parser: TimestampStreamParser,
     reader: R,
+    error: Option<DecodeError>,
 }
 
 impl<R> TimestampStreamIterator<R> where R: Reader {
     pub fn new(reader: R, header_time: u64) -> Self {
+        TimestampStreamIterator::with_format(reader, header_time, TimestampFormat::default())
+    }
+
+    pub fn with_format(reader: R, header_time: u64, format: TimestampFormat) -> Self {
         TimestampStreamIterator {
-            parser: TimestampStreamParser::new(header_time),
-            reader: reader,
+            parser: TimestampStreamParser::with_format(header_time, format),
+            reader,
+            error: None,
         }
     }
+
+    pub fn error(&self) -> Option<&DecodeError> {
+        self.error.as_ref()
+    }
+}
+
+impl<'a> TimestampStreamIterator<VecReader<'a>> {
+    /// Decodes timestamps into `out` until it is full or the stream ends, and
+    /// returns how many were decoded, reading through a `BufferedVecReader`.
+    /// See `TimestampStreamParser::decode_into`; unlike it, an error is
+    /// available from `error` as soon as the timestamps before it have been
+    /// decoded.
+    pub fn decode_into(&mut self, out: &mut [u64]) -> usize {
+        if self.error.is_some() {
+            return 0;
+        }
+
+        let mut buffered = self.reader.buffered();
+        let count = match self.parser.decode_into(&mut buffered, out) {
+            Ok(count) if count < out.len() => {
+                // the end of the stream, or an error after `count` values
+                self.error = self.parser.next(&mut buffered).err();
+                count
+            },
+            Ok(count) => count,
+            Err(error) => {
+                self.error = Some(error);
+                0
+            }
+        };
+
+        self.reader.seek(buffered.position());
+        count
+    }
 }
 
 impl<R> Iterator for TimestampStreamIterator<R> where R: Reader {
     type Item = u64;
 
     fn next(&mut self) -> Option<u64> {
-        self.parser.next(&mut self.reader)
+        if self.error.is_some() {
+            return None;
+        }
+
+        self.parser.next(&mut self.reader).unwrap_or_else(|error| {
+            self.error = Some(error);
+            None
+        })
     }
 }
 
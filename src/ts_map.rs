@@ -0,0 +1,236 @@
+//! A map of many series by name, like the TSmap in the Gorilla paper. It keeps
+//! a vector of series and a map from name to index into that vector. A
+//! read-write lock protects both, while each series has a lock of its own, so
+//! appends to different series can happen from multiple threads at once.
+//!
+//! Scans take a snapshot of the vector, which only holds the read lock while
+//! copying it. Slots of deleted series are reused by new series.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, MutexGuard, RwLock};
+
+use series::{self, Series, DEFAULT_WINDOW};
+use timestamp_stream::TimestampFormat;
+use error::EncodeError;
+
+pub struct TsEntry {
+    id: usize,
+    name: String,
+    series: Mutex<Series>,
+}
+
+impl TsEntry {
+    /// The index of the series in the map. Ids of deleted series are reused.
+    pub fn id(&self) -> usize {
+        self.id
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn lock(&self) -> MutexGuard<'_, Series> {
+        self.series.lock().unwrap()
+    }
+}
+
+struct Slots {
+    entries: Vec<Option<Arc<TsEntry>>>,
+    index: HashMap<String, usize>,
+    free: Vec<usize>,
+}
+
+pub struct TsMap {
+    slots: RwLock<Slots>,
+    window: u64,
+    format: TimestampFormat,
+}
+
+impl TsMap {
+    pub fn new() -> Self {
+        TsMap::with_format(DEFAULT_WINDOW, TimestampFormat::default())
+    }
+
+    /// New series are created with `Series::with_format(window, format)`.
+    /// Panics if `window` is zero or too long for `format`, rather than when
+    /// the first series is created.
+    pub fn with_format(window: u64, format: TimestampFormat) -> Self {
+        series::assert_window(window, format);
+        TsMap {
+            slots: RwLock::new(Slots {
+                entries: Vec::new(),
+                index: HashMap::new(),
+                free: Vec::new(),
+            }),
+            window,
+            format,
+        }
+    }
+
+    pub fn get(&self, name: &str) -> Option<Arc<TsEntry>> {
+        let slots = self.slots.read().unwrap();
+        slots.index.get(name).and_then(|&id| slots.entries[id].clone())
+    }
+
+    pub fn get_by_id(&self, id: usize) -> Option<Arc<TsEntry>> {
+        let slots = self.slots.read().unwrap();
+        slots.entries.get(id).and_then(|entry| entry.clone())
+    }
+
+    /// Returns the series called `name`, creating it if it doesn't exist.
+    pub fn get_or_insert(&self, name: &str) -> Arc<TsEntry> {
+        if let Some(entry) = self.get(name) {
+            return entry;
+        }
+
+        let mut slots = self.slots.write().unwrap();
+        // another thread may have inserted it while the lock was released
+        if let Some(&id) = slots.index.get(name) {
+            return slots.entries[id].clone().unwrap();
+        }
+
+        let id = match slots.free.pop() {
+            Some(id) => id,
+            None => {
+                slots.entries.push(None);
+                slots.entries.len() - 1
+            }
+        };
+
+        let entry = Arc::new(TsEntry {
+            id,
+            name: name.to_string(),
+            series: Mutex::new(Series::with_format(self.window, self.format)),
+        });
+        slots.entries[id] = Some(entry.clone());
+        slots.index.insert(name.to_string(), id);
+        entry
+    }
+
+    /// Appends a sample to the series called `name`, creating it if needed.
+    pub fn push(&self, name: &str, timestamp: u64, value: f64) -> Result<(), EncodeError> {
+        self.get_or_insert(name).lock().push(timestamp, value)
+    }
+
+    /// Deletes the series called `name` and frees its slot. Returns the
+    /// deleted series, which may still be referenced by earlier snapshots.
+    pub fn remove(&self, name: &str) -> Option<Arc<TsEntry>> {
+        let mut slots = self.slots.write().unwrap();
+        let id = slots.index.remove(name)?;
+        slots.free.push(id);
+        slots.entries[id].take()
+    }
+
+    /// A copy of the current list of series, for scanning without holding the
+    /// lock on the map.
+    pub fn snapshot(&self) -> Vec<Arc<TsEntry>> {
+        let slots = self.slots.read().unwrap();
+        slots.entries.iter().filter_map(|entry| entry.clone()).collect()
+    }
+
+    pub fn len(&self) -> usize {
+        self.slots.read().unwrap().index.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl Default for TsMap {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    #[test]
+    fn push_and_get() {
+        let map = TsMap::new();
+        map.push("cpu", 1496366523, 0.5).unwrap();
+        map.push("cpu", 1496366583, 0.7).unwrap();
+        map.push("memory", 1496366523, 1024.0).unwrap();
+
+        assert_eq!(map.len(), 2);
+        assert!(map.get("disk").is_none());
+
+        let cpu = map.get("cpu").unwrap();
+        assert_eq!(cpu.name(), "cpu");
+        assert_eq!(cpu.lock().iter().collect::<Vec<_>>(), vec![(1496366523, 0.5), (1496366583, 0.7)]);
+        assert_eq!(map.get_by_id(cpu.id()).unwrap().name(), "cpu");
+    }
+
+    #[test]
+    fn reuse_slots() {
+        let map = TsMap::new();
+        let a = map.get_or_insert("a").id();
+        let b = map.get_or_insert("b").id();
+        assert_ne!(a, b);
+
+        let removed = map.remove("a").unwrap();
+        assert_eq!(removed.name(), "a");
+        assert!(map.get("a").is_none());
+        assert!(map.remove("a").is_none());
+        assert_eq!(map.len(), 1);
+
+        assert_eq!(map.get_or_insert("c").id(), a);
+        assert!(map.get_or_insert("c").lock().iter().next().is_none());
+        assert_eq!(map.len(), 2);
+    }
+
+    #[test]
+    fn snapshot() {
+        let map = TsMap::new();
+        map.push("a", 10, 1.0).unwrap();
+        map.push("b", 10, 2.0).unwrap();
+
+        let snapshot = map.snapshot();
+        map.remove("a");
+        map.push("c", 10, 3.0).unwrap();
+
+        // the snapshot is unaffected by later changes to the map
+        let names: Vec<&str> = snapshot.iter().map(|entry| entry.name()).collect();
+        assert_eq!(names, vec!["a", "b"]);
+        assert_eq!(snapshot[0].lock().iter().collect::<Vec<_>>(), vec![(10, 1.0)]);
+
+        let names: Vec<String> = map.snapshot().iter().map(|entry| entry.name().to_string()).collect();
+        assert_eq!(names, vec!["c", "b"]);
+    }
+
+    #[test]
+    #[should_panic]
+    fn window_too_long() {
+        TsMap::with_format((1 << 14) + 1, TimestampFormat::default());
+    }
+
+    #[test]
+    fn concurrent_appends() {
+        let map = Arc::new(TsMap::new());
+
+        let threads: Vec<_> = (0..8).map(|t| {
+            let map = map.clone();
+            thread::spawn(move || {
+                let name = format!("series-{}", t % 4);
+                for i in 0..1_000 {
+                    // two threads share each series, so only check that the pushes don't fail when ordered
+                    let _ = map.push(&name, 1496366523 + i, i as f64);
+                    map.push(&format!("thread-{}", t), 1496366523 + i, i as f64).unwrap();
+                }
+            })
+        }).collect();
+
+        for thread in threads {
+            thread.join().unwrap();
+        }
+
+        assert_eq!(map.len(), 12);
+        for t in 0..8 {
+            let entry = map.get(&format!("thread-{}", t)).unwrap();
+            assert_eq!(entry.lock().iter().count(), 1_000);
+        }
+    }
+}
@@ -0,0 +1,571 @@
+//! Compatibility with the bit streams of [go-tsz](https://github.com/dgryski/go-tsz),
+//! so series written by it can be read and series can be written for it. The
+//! layout is close to, but not the same as, `TimeAndValueStream`:
+//!
+//! - The stream starts with a 32-bit header time, `t0`, and timestamps are
+//!   32-bit seconds.
+//! - Delta of deltas are stored in two's complement in buckets of 7, 9 and 12
+//!   bits, covering `-63..=64`, `-255..=256` and `-2047..=2048`, or else in
+//!   32 bits.
+//! - Values keep the leading and trailing zeros of the last window, and store
+//!   the number of meaningful bits `M` as `M & 63` (see `[MEANING64]`).
+//! - A finished stream ends with `1111`, 32 ones and a zero, which can't be
+//!   a delta of deltas since -1 fits in the smallest bucket.
+//!
+//! go-tsz takes a timestamp of 0 to mean that no sample has been written, so
+//! series with `t0` 0 should not start with a sample at 0.
+
+pub use stream::{Writer, Reader, ValueEncoder, ValueDecoder};
+pub use error::{DecodeError, EncodeError};
+use error::{read_control, read_payload};
+
+const FIRST_DELTA_BITS: u8 = 14;
+const BUCKET_BITS: [u8; 3] = [7, 9, 12];
+const END_OF_STREAM: u64 = 0xFFFFFFFF;
+
+/// The leading zeros before any window has been written.
+const NO_WINDOW: u8 = 0xFF;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TszValueState {
+    Initial,
+    Following {
+        value: u64,
+        leading_zeros: u8,
+        trailing_zeros: u8,
+    }
+}
+
+/// Compresses values the way go-tsz does. This is also the value encoding of
+/// Prometheus XOR chunks.
+pub struct TszValueWriter {
+    state: TszValueState,
+}
+
+impl TszValueWriter {
+    pub fn new() -> Self {
+        TszValueWriter {
+            state: TszValueState::Initial
+        }
+    }
+
+    pub fn push(&mut self, number: f64, writer: &mut dyn Writer) {
+        let number_as_bits = number.to_bits();
+
+        self.state = match self.state {
+            TszValueState::Initial => {
+                writer.write(number_as_bits, 64);
+                TszValueState::Following { value: number_as_bits, leading_zeros: NO_WINDOW, trailing_zeros: 0 }
+            },
+            TszValueState::Following { value: previous, leading_zeros: prev_lz, trailing_zeros: prev_tz } => {
+                let xored = previous ^ number_as_bits;
+                if xored == 0 {
+                    writer.write(0, 1);
+                    TszValueState::Following { value: number_as_bits, leading_zeros: prev_lz, trailing_zeros: prev_tz }
+                } else {
+                    let lz = (xored.leading_zeros() as u8).min(31); // [LEADING31]
+                    let tz = xored.trailing_zeros() as u8;
+
+                    if prev_lz != NO_WINDOW && lz >= prev_lz && tz >= prev_tz {
+                        writer.write(0b10, 2);
+                        writer.write(xored >> prev_tz, 64 - prev_lz - prev_tz);
+                        TszValueState::Following { value: number_as_bits, leading_zeros: prev_lz, trailing_zeros: prev_tz }
+                    } else {
+                        let meaningful_bit_count = 64 - lz - tz;
+                        writer.write(0b11, 2);
+                        writer.write(lz as u64, 5);
+                        writer.write((meaningful_bit_count & 63) as u64, 6); // [MEANING64]
+                        writer.write(xored >> tz, meaningful_bit_count);
+                        TszValueState::Following { value: number_as_bits, leading_zeros: lz, trailing_zeros: tz }
+                    }
+                }
+            }
+        };
+    }
+}
+
+impl Default for TszValueWriter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ValueEncoder for TszValueWriter {
+    fn push(&mut self, number: f64, writer: &mut dyn Writer) {
+        TszValueWriter::push(self, number, writer)
+    }
+}
+
+pub struct TszValueParser {
+    state: TszValueState,
+}
+
+impl TszValueParser {
+    pub fn new() -> Self {
+        TszValueParser {
+            state: TszValueState::Initial
+        }
+    }
+
+    /// Continues decoding after a first value read by other means.
+    pub fn set_first(&mut self, value: u64) {
+        // go-tsz starts reading with an empty window, which is never reused
+        // by its writer
+        self.state = TszValueState::Following { value, leading_zeros: 0, trailing_zeros: 0 };
+    }
+
+    /// Returns `Ok(None)` at the end of the stream, or an error if the stream
+    /// ends in the middle of a value or is otherwise malformed.
+    pub fn next(&mut self, reader: &mut dyn Reader) -> Result<Option<f64>, DecodeError> {
+        let (value, leading_zeros, trailing_zeros) = match self.state {
+            TszValueState::Initial => {
+                match reader.read(64) {
+                    Some(value) => (value, 0, 0),
+                    None => return Ok(None),
+                }
+            },
+            TszValueState::Following { value, leading_zeros, trailing_zeros } => {
+                let offset = reader.position();
+                match reader.read(1) {
+                    None => return Ok(None),
+                    Some(0) => (value, leading_zeros, trailing_zeros),
+                    Some(_) => {
+                        let (lz, tz) = if read_control(reader, 1)? == 0 {
+                            (leading_zeros, trailing_zeros)
+                        } else {
+                            let lz = read_payload(reader, 5)? as u8;
+                            let meaningful_bit_count = match read_payload(reader, 6)? as u8 {
+                                0 => 64, // [MEANING64]
+                                count => count,
+                            };
+                            if lz + meaningful_bit_count > 64 {
+                                return Err(DecodeError::InvalidWindow { offset, leading_zeros: lz, meaningful_bits: meaningful_bit_count });
+                            }
+                            (lz, 64 - lz - meaningful_bit_count)
+                        };
+
+                        let xored = read_payload(reader, 64 - lz - tz)? << tz;
+                        (value ^ xored, lz, tz)
+                    }
+                }
+            }
+        };
+
+        self.state = TszValueState::Following { value, leading_zeros, trailing_zeros };
+        Ok(Some(f64::from_bits(value)))
+    }
+}
+
+impl Default for TszValueParser {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ValueDecoder for TszValueParser {
+    fn next(&mut self, reader: &mut dyn Reader) -> Result<Option<f64>, DecodeError> {
+        TszValueParser::next(self, reader)
+    }
+}
+
+/// Writes a go-tsz series: the header on creation, then a sample per `push`,
+/// and the end of stream marker on `finish`.
+pub struct TszWriter {
+    t0: u32,
+    last: Option<(u32, u32)>,
+    values: TszValueWriter,
+}
+
+impl TszWriter {
+    pub fn new(t0: u32, writer: &mut dyn Writer) -> Self {
+        writer.write(t0 as u64, 32);
+
+        TszWriter {
+            t0,
+            last: None,
+            values: TszValueWriter::new(),
+        }
+    }
+
+    pub fn t0(&self) -> u32 {
+        self.t0
+    }
+
+    /// Panics if the timestamp can't be encoded. See `try_push`.
+    pub fn push(&mut self, timestamp: u32, value: f64, writer: &mut dyn Writer) {
+        if let Err(error) = self.try_push(timestamp, value, writer) {
+            panic!("{}", error);
+        }
+    }
+
+    /// Encodes a sample, or returns an error without writing anything if the
+    /// timestamp is before `t0` or the previous timestamp, or the first one is
+    /// more than 14 bits after `t0`.
+    pub fn try_push(&mut self, timestamp: u32, value: f64, writer: &mut dyn Writer) -> Result<(), EncodeError> {
+        let delta = match self.last {
+            None => {
+                if timestamp < self.t0 {
+                    return Err(EncodeError::BeforeHeader { timestamp: timestamp as u64, header_time: self.t0 as u64 });
+                }
+                let delta = timestamp - self.t0;
+                let max = (1 << FIRST_DELTA_BITS) - 1;
+                if delta > max {
+                    return Err(EncodeError::FirstDeltaTooLarge { delta: delta as u64, max: max as u64 });
+                }
+                writer.write(delta as u64, FIRST_DELTA_BITS);
+                delta
+            },
+            Some((previous, prev_delta)) => {
+                if timestamp < previous {
+                    return Err(EncodeError::OutOfOrder { timestamp: timestamp as u64, previous: previous as u64 });
+                }
+                let delta = timestamp - previous;
+                let delta_of_deltas = delta.wrapping_sub(prev_delta) as i32 as i64;
+
+                if delta_of_deltas == 0 {
+                    writer.write(0, 1);
+                } else if let Some(bucket) = BUCKET_BITS.iter().position(|&bits| fits_bucket(delta_of_deltas, bits)) {
+                    // 10, 110 or 1110
+                    let bits = BUCKET_BITS[bucket];
+                    writer.write((1 << (bucket + 2)) - 2, bucket as u8 + 2);
+                    writer.write(delta_of_deltas as u64 & ((1 << bits) - 1), bits);
+                } else {
+                    writer.write(0b1111, 4);
+                    writer.write(delta_of_deltas as u64 & 0xFFFFFFFF, 32);
+                }
+                delta
+            },
+        };
+
+        self.values.push(value, writer);
+        self.last = Some((timestamp, delta));
+        Ok(())
+    }
+
+    /// Writes the end of stream marker. Nothing should be written after it.
+    pub fn finish(self, writer: &mut dyn Writer) {
+        writer.write(0b1111, 4);
+        writer.write(END_OF_STREAM, 32);
+        writer.write(0, 1);
+    }
+}
+
+/// go-tsz buckets are one larger on the positive side, see the module docs.
+pub(crate) fn fits_bucket(delta_of_deltas: i64, bits: u8) -> bool {
+    let half = 1 << (bits - 1);
+    delta_of_deltas > -half && delta_of_deltas <= half
+}
+
+enum TszState {
+    Header,
+    First { t0: u32 },
+    Following { timestamp: u32, delta: u32 },
+    Finished,
+}
+
+pub struct TszParser {
+    state: TszState,
+    t0: Option<u32>,
+    values: TszValueParser,
+}
+
+impl TszParser {
+    pub fn new() -> Self {
+        TszParser {
+            state: TszState::Header,
+            t0: None,
+            values: TszValueParser::new(),
+        }
+    }
+
+    /// The header time, once it has been read.
+    pub fn t0(&self) -> Option<u32> {
+        self.t0
+    }
+
+    /// Returns `Ok(None)` at the end of stream marker, or at the end of the
+    /// reader if the stream isn't finished yet. Returns an error if the
+    /// stream ends in the middle of a sample.
+    pub fn next(&mut self, reader: &mut dyn Reader) -> Result<Option<(u64, f64)>, DecodeError> {
+        if let TszState::Header = self.state {
+            let t0 = read_payload(reader, 32)? as u32;
+            self.t0 = Some(t0);
+            self.state = TszState::First { t0 };
+        }
+
+        let offset = reader.position();
+        let (timestamp, delta) = match self.state {
+            TszState::Header | TszState::Finished => return Ok(None),
+            TszState::First { t0 } => {
+                let delta = match reader.read(FIRST_DELTA_BITS) {
+                    Some(delta) => delta,
+                    None => return Ok(None),
+                };
+
+                // a finished stream without samples has the end of stream
+                // marker where the first sample would be. A first delta of
+                // 16383 followed by a value starting with the rest of the
+                // marker is also valid, but then the rest of the value
+                // follows, while nothing does after the marker.
+                let value = if delta == (1 << FIRST_DELTA_BITS) - 1 {
+                    let marker_bits = 4 + 32 + 1 - FIRST_DELTA_BITS;
+                    let high_bits = read_payload(reader, marker_bits)?;
+                    let low_bits = if high_bits == (1 << marker_bits) - 2 {
+                        match reader.read(64 - marker_bits) {
+                            Some(low_bits) => low_bits,
+                            None => {
+                                self.state = TszState::Finished;
+                                return Ok(None);
+                            }
+                        }
+                    } else {
+                        read_payload(reader, 64 - marker_bits)?
+                    };
+                    (high_bits << (64 - marker_bits)) | low_bits
+                } else {
+                    read_payload(reader, 64)?
+                };
+                self.values.set_first(value);
+
+                let delta = delta as u32;
+                self.state = TszState::Following { timestamp: t0.wrapping_add(delta), delta };
+                return Ok(Some((t0.wrapping_add(delta) as u64, f64::from_bits(value))));
+            },
+            TszState::Following { timestamp, delta } => {
+                match reader.read(1) {
+                    None => return Ok(None),
+                    Some(0) => (timestamp.wrapping_add(delta), delta),
+                    Some(_) => {
+                        // count the ones following the first one: 10, 110, 1110 or 1111
+                        let mut bucket = 0;
+                        while bucket < 3 && read_control(reader, 1)? == 1 {
+                            bucket += 1;
+                        }
+
+                        let delta_of_deltas = if bucket < 3 {
+                            let bits = BUCKET_BITS[bucket];
+                            let payload = read_payload(reader, bits)? as i64;
+                            if payload > 1 << (bits - 1) { payload - (1 << bits) } else { payload }
+                        } else {
+                            let payload = read_payload(reader, 32)?;
+                            if payload == END_OF_STREAM {
+                                self.state = TszState::Finished;
+                                return Ok(None);
+                            }
+                            payload as u32 as i32 as i64
+                        };
+
+                        let delta = delta.wrapping_add(delta_of_deltas as u32);
+                        (timestamp.wrapping_add(delta), delta)
+                    }
+                }
+            },
+        };
+
+        let value = match self.values.next(reader)? {
+            Some(value) => value,
+            None => return Err(DecodeError::TruncatedPayload { offset }),
+        };
+
+        self.state = TszState::Following { timestamp, delta };
+        Ok(Some((timestamp as u64, value)))
+    }
+}
+
+impl Default for TszParser {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Iterates over the samples of a go-tsz series. Iteration stops at the first
+/// decode error, which is then available from `error`.
+pub struct TszIterator<R> where R: Reader {
+    parser: TszParser,
+    reader: R,
+    error: Option<DecodeError>,
+}
+
+impl<R> TszIterator<R> where R: Reader {
+    pub fn new(reader: R) -> Self {
+        TszIterator {
+            parser: TszParser::new(),
+            reader,
+            error: None,
+        }
+    }
+
+    /// The header time, once the first sample has been read.
+    pub fn t0(&self) -> Option<u32> {
+        self.parser.t0()
+    }
+
+    pub fn error(&self) -> Option<&DecodeError> {
+        self.error.as_ref()
+    }
+}
+
+impl<R> Iterator for TszIterator<R> where R: Reader {
+    type Item = (u64, f64);
+
+    fn next(&mut self) -> Option<(u64, f64)> {
+        if self.error.is_some() {
+            return None;
+        }
+
+        self.parser.next(&mut self.reader).unwrap_or_else(|error| {
+            self.error = Some(error);
+            None
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use vec_stream::VecWriter;
+    use slice_stream::SliceReader;
+
+    const T0: u32 = 1500000000;
+
+    fn samples() -> Vec<(u32, f64)> {
+        vec![
+            (T0 + 60, 100.0),
+            (T0 + 120, 100.0),
+            (T0 + 181, 101.5),
+            (T0 + 240, 99.25),
+            (T0 + 300, -0.0),
+            (T0 + 360, 1e300),
+            (T0 + 600, 1e300),
+            (T0 + 601, 2.0),
+            (T0 + 5000, 2.5),
+        ]
+    }
+
+    // go-tsz's encoding of `samples`, as printed by go-tsz commit 4815cfd8 with
+    //
+    //     cd testdata/golden
+    //     go get github.com/dgryski/go-tsz@4815cfd89fc090a7bef4a8fc0cb0f5695a23ceaa
+    //     go run ./tsz
+    //
+    // These bytes were worked out from the source of that commit and have not
+    // been regenerated with the command yet. Paste its output over them, and
+    // commit the go.mod and go.sum it leaves behind.
+    const GOLDEN: [u8; 64] = [
+        0x59, 0x68, 0x2f, 0x00, 0x00, 0xf1, 0x01, 0x64, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x80, 0xf1,
+        0x0b, 0xbf, 0x6f, 0x17, 0x70, 0x1c, 0x0a, 0x60, 0x2c, 0x6b, 0x07, 0xdf, 0xc6, 0xfc, 0x87, 0x91,
+        0x00, 0x0e, 0xb3, 0xe5, 0xa3, 0x44, 0x63, 0xe3, 0x7e, 0x43, 0xc8, 0x80, 0x07, 0x59, 0xfc, 0x00,
+        0x00, 0x44, 0xba, 0x00, 0x04, 0x00, 0x00, 0x00, 0x00, 0x00, 0x03, 0xff, 0xff, 0xff, 0xff, 0xc0,
+    ];
+
+    fn written(samples: &[(u32, f64)]) -> VecWriter {
+        let mut w = VecWriter::new();
+        let mut c = TszWriter::new(T0, &mut w);
+        for &(timestamp, value) in samples {
+            c.push(timestamp, value, &mut w);
+        }
+        c.finish(&mut w);
+        w
+    }
+
+    fn read(bytes: &[u8]) -> Vec<(u64, f64)> {
+        let mut r = TszIterator::new(SliceReader::new(bytes, bytes.len() * 8));
+        let samples = r.by_ref().collect();
+        assert_eq!(r.error(), None);
+        assert_eq!(r.t0(), Some(T0));
+        samples
+    }
+
+    fn as_u64(samples: &[(u32, f64)]) -> Vec<(u64, f64)> {
+        samples.iter().map(|&(timestamp, value)| (timestamp as u64, value)).collect()
+    }
+
+    #[test]
+    fn write_golden() {
+        let w = written(&samples());
+        assert_eq!(w.len(), 507);
+        assert_eq!(w.to_bytes(), GOLDEN.to_vec());
+    }
+
+    #[test]
+    fn read_golden() {
+        assert_eq!(read(&GOLDEN), as_u64(&samples()));
+    }
+
+    #[test]
+    fn one_sample() {
+        let golden = [0x59, 0x68, 0x2f, 0x00, 0x00, 0x28, 0xff, 0xc0, 0x00, 0x00, 0x00, 0x00, 0x00, 0x03, 0xff, 0xff, 0xff, 0xff, 0xc0];
+        assert_eq!(written(&[(T0 + 10, 1.0)]).to_bytes(), golden.to_vec());
+        assert_eq!(read(&golden), vec![(T0 as u64 + 10, 1.0)]);
+    }
+
+    #[test]
+    fn empty() {
+        let golden = [0x59, 0x68, 0x2f, 0x00, 0xff, 0xff, 0xff, 0xff, 0xf0];
+        assert_eq!(written(&[]).to_bytes(), golden.to_vec());
+        assert_eq!(read(&golden), vec![]);
+    }
+
+    #[test]
+    fn largest_first_delta() {
+        // the first 23 bits of these values, after a first delta of 16383,
+        // look like the end of stream marker or most of it
+        for &value in [f64::from_bits(!0), f64::from_bits(0xFFFFFC0000000000), f64::from_bits(0xFFFFFDFFFFFFFFFF)].iter() {
+            let samples = [(T0 + 16383, value), (T0 + 16443, 1.0)];
+            let w = written(&samples);
+            let bits: Vec<(u64, u64)> = read(&w.to_bytes()).iter().map(|&(timestamp, value)| (timestamp, value.to_bits())).collect();
+            assert_eq!(bits, vec![(T0 as u64 + 16383, value.to_bits()), (T0 as u64 + 16443, 1f64.to_bits())]);
+
+            let w = written(&samples[..1]);
+            assert_eq!(read(&w.to_bytes()).len(), 1);
+        }
+    }
+
+    #[test]
+    fn unfinished() {
+        let mut w = VecWriter::new();
+        let mut c = TszWriter::new(T0, &mut w);
+        for &(timestamp, value) in samples().iter() {
+            c.push(timestamp, value, &mut w);
+        }
+
+        // without the marker, the exact number of bits is needed
+        let bytes = w.to_bytes();
+        let r = TszIterator::new(SliceReader::new(&bytes, w.len()));
+        assert_eq!(r.collect::<Vec<_>>(), as_u64(&samples()));
+    }
+
+    #[test]
+    fn truncated() {
+        // the fourth sample takes bits 136 to 163
+        let mut r = TszIterator::new(SliceReader::new(&GOLDEN, 150));
+        assert_eq!(r.by_ref().count(), 3);
+        assert!(r.error().is_some());
+    }
+
+    #[test]
+    fn bucket_edges() {
+        // delta of deltas at the edges of each bucket, both ways
+        let mut samples = vec![(T0 + 10, 0.0)];
+        let mut delta = 10i64;
+        for &dod in [64, -63, 256, -255, 2048, -2047, 2049, -2048, 65, -64].iter() {
+            delta += dod;
+            let previous = samples.last().unwrap().0;
+            samples.push((previous + delta as u32, dod as f64));
+        }
+
+        let w = written(&samples);
+        assert_eq!(read(&w.to_bytes()), as_u64(&samples));
+    }
+
+    #[test]
+    fn try_push_errors() {
+        let mut w = VecWriter::new();
+        let mut c = TszWriter::new(T0, &mut w);
+        assert_eq!(c.try_push(T0 - 1, 0.0, &mut w), Err(EncodeError::BeforeHeader { timestamp: T0 as u64 - 1, header_time: T0 as u64 }));
+        assert_eq!(c.try_push(T0 + 16384, 0.0, &mut w), Err(EncodeError::FirstDeltaTooLarge { delta: 16384, max: 16383 }));
+        c.try_push(T0 + 100, 0.0, &mut w).unwrap();
+        assert_eq!(c.try_push(T0 + 99, 0.0, &mut w), Err(EncodeError::OutOfOrder { timestamp: T0 as u64 + 99, previous: T0 as u64 + 100 }));
+        assert_eq!(w.len(), 32 + 14 + 64);
+    }
+}
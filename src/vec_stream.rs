@@ -1,7 +1,8 @@
 //! This module provides a writer and writer for bits into a binary vector that
 //! will expand as needed.
 
-pub use stream::{Writer, Reader};
+pub use stream::{Writer, Reader, SeekableReader};
+use std::cmp;
 
 pub struct VecWriter {
     pub bit_vector: Vec<u64>,
@@ -19,6 +20,24 @@ impl VecWriter {
     pub fn len(&self) -> usize {
         (self.bit_vector.len() - 1) * 64 + (self.used_bits_last_elm as usize)
     }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The written bits as bytes, most significant bit first, padded with
+    /// zeros to a whole byte. This is the layout read by `SliceReader`.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes: Vec<u8> = self.bit_vector.iter().flat_map(|word| word.to_be_bytes().to_vec()).collect();
+        bytes.truncate(self.len().div_ceil(8));
+        bytes
+    }
+}
+
+impl Default for VecWriter {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Writer for VecWriter {
@@ -46,6 +65,10 @@ impl Writer for VecWriter {
             }
         }
     }
+
+    fn position(&self) -> usize {
+        self.len()
+    }
 }
 
 pub struct VecReader<'a> {
@@ -61,16 +84,25 @@ impl<'a> VecReader<'a> {
             bit_vector: data,
             index: 0,
             read_bits_current_index: 0,
-            num_bits_last_elm: num_bits_last_elm,
+            num_bits_last_elm,
         }
     }
+
+    /// A `BufferedVecReader` at the current position, for decoding a run of
+    /// values without going through `read`. Seek back to its position
+    /// afterwards.
+    pub(crate) fn buffered(&self) -> BufferedVecReader<'a> {
+        let mut reader = BufferedVecReader::new(self.bit_vector, self.num_bits_last_elm);
+        reader.seek(self.position());
+        reader
+    }
 }
 
 impl<'a> Reader for VecReader<'a> {
     fn read(&mut self, count: u8) -> Option<u64> {
-        if self.bit_vector.len() == 0 {
-            // TODO: would be nice if we could avoid this test every time. See
-            // `read_empty_vector` for when it's needed.
+        if self.bit_vector.is_empty() {
+            // See `read_empty_vector` for when this test is needed.
+            // `BufferedVecReader` avoids it, along with the bookkeeping below.
             return None;
         }
 
@@ -86,7 +118,7 @@ impl<'a> Reader for VecReader<'a> {
                 let current_index = self.index;
                 self.read_bits_current_index += count;
 
-                if (self.read_bits_current_index == 64) {
+                if self.read_bits_current_index == 64 {
                     self.index += 1;
                     self.read_bits_current_index = 0;
                 }
@@ -107,6 +139,110 @@ impl<'a> Reader for VecReader<'a> {
             }
         }
     }
+
+    fn position(&self) -> usize {
+        self.index * 64 + self.read_bits_current_index as usize
+    }
+}
+
+impl<'a> SeekableReader for VecReader<'a> {
+    fn seek(&mut self, position: usize) {
+        let total_bits = match self.bit_vector.len() {
+            0 => 0,
+            len => (len - 1) * 64 + self.num_bits_last_elm as usize,
+        };
+        let position = cmp::min(position, total_bits);
+        self.index = position / 64;
+        self.read_bits_current_index = (position % 64) as u8;
+    }
+}
+
+/// A faster alternative to `VecReader`, which reads a word at a time into a
+/// 128-bit buffer. Reads are served from the buffer without any other checks
+/// as long as it holds enough bits, and it is refilled only when it doesn't.
+pub struct BufferedVecReader<'a> {
+    bit_vector: &'a [u64],
+    next_index: usize,
+    /// unread bits, most significant first
+    buffer: u128,
+    buffered_bits: u8,
+    total_bits: usize,
+}
+
+impl<'a> BufferedVecReader<'a> {
+    pub fn new(data: &'a [u64], num_bits_last_elm: u8) -> Self {
+        let total_bits = match data.len() {
+            0 => 0,
+            len => (len - 1) * 64 + num_bits_last_elm as usize,
+        };
+
+        BufferedVecReader {
+            bit_vector: data,
+            next_index: 0,
+            buffer: 0,
+            buffered_bits: 0,
+            total_bits,
+        }
+    }
+
+    /// Appends the next word to the buffer, if there is room for it.
+    #[inline]
+    fn refill(&mut self) {
+        if self.buffered_bits <= 64 {
+            if let Some(&word) = self.bit_vector.get(self.next_index) {
+                let loaded_bits = self.next_index * 64;
+                self.buffer |= (word as u128) << (64 - self.buffered_bits);
+                self.buffered_bits += cmp::min(64, self.total_bits - loaded_bits) as u8;
+                self.next_index += 1;
+            }
+        }
+    }
+
+    #[inline]
+    fn take(&mut self, count: u8) -> u64 {
+        let bits = (self.buffer >> (128 - count as u32)) as u64;
+        self.buffer <<= count;
+        self.buffered_bits -= count;
+        bits
+    }
+}
+
+impl<'a> Reader for BufferedVecReader<'a> {
+    #[inline]
+    fn read(&mut self, count: u8) -> Option<u64> {
+        if count == 0 {
+            return Some(0);
+        }
+        if count <= self.buffered_bits {
+            return Some(self.take(count));
+        }
+
+        self.refill();
+        if count <= self.buffered_bits {
+            Some(self.take(count))
+        } else {
+            None
+        }
+    }
+
+    fn position(&self) -> usize {
+        cmp::min(self.next_index * 64, self.total_bits) - self.buffered_bits as usize
+    }
+}
+
+impl<'a> SeekableReader for BufferedVecReader<'a> {
+    fn seek(&mut self, position: usize) {
+        let position = cmp::min(position, self.total_bits);
+        self.next_index = position / 64;
+        self.buffer = 0;
+        self.buffered_bits = 0;
+
+        let offset = (position % 64) as u8;
+        if offset > 0 {
+            self.refill();
+            self.take(offset);
+        }
+    }
 }
 
 #[cfg(test)]
@@ -147,6 +283,79 @@ mod tests {
         assert_eq!(w.bit_vector[1], 0b0100000000000000000000000000000000000000000000000000000000000000);
     }
 
+    #[test]
+    fn to_bytes() {
+        let mut w = VecWriter::new();
+        assert_eq!(w.to_bytes(), vec![]);
+        w.write(0b101, 3);
+        assert_eq!(w.to_bytes(), vec![0b10100000]);
+        w.write(0x0123456789ABCDEF, 64);
+        assert_eq!(w.to_bytes(), vec![0b10100000, 0x24, 0x68, 0xAC, 0xF1, 0x35, 0x79, 0xBD, 0xE0]);
+    }
+
+    #[test]
+    fn seek() {
+        let mut w = VecWriter::new();
+        w.write(0x0123456789ABCDEF, 64);
+        w.write(0b1011, 4);
+
+        let mut r = VecReader::new(&w.bit_vector, w.used_bits_last_elm);
+        r.seek(60);
+        assert_eq!(r.read(8), Some(0xFB));
+        r.seek(4);
+        assert_eq!(r.position(), 4);
+        assert_eq!(r.read(4), Some(0x1));
+        r.seek(64);
+        assert_eq!(r.read(4), Some(0b1011));
+        assert_eq!(r.read(1), None);
+        r.seek(1000);
+        assert_eq!(r.position(), 68);
+        assert_eq!(r.read(1), None);
+    }
+
+    #[test]
+    fn buffered() {
+        let mut w = VecWriter::new();
+        for i in 0..40u64 {
+            let count = (i % 64) as u8 + 1;
+            w.write(i.wrapping_mul(0x9E3779B97F4A7C15) >> (64 - count), count);
+        }
+
+        let mut r = VecReader::new(&w.bit_vector, w.used_bits_last_elm);
+        r.read(13);
+        let mut b = r.buffered();
+        assert_eq!(b.position(), 13);
+        for &count in [51, 64, 1, 63, 64, 7, 64].iter().cycle().take(40) {
+            assert_eq!(b.read(count), r.read(count));
+            assert_eq!(b.position(), r.position());
+        }
+        assert_eq!(b.read(64), None);
+        assert_eq!(b.read(0), Some(0));
+
+        b.seek(70);
+        r.seek(70);
+        assert_eq!(b.read(60), r.read(60));
+        b.seek(w.len() - 3);
+        r.seek(w.len() - 3);
+        assert_eq!(b.read(4), None);
+        assert_eq!(b.read(3), r.read(3));
+    }
+
+    #[test]
+    fn buffered_truncated() {
+        // bits past `num_bits_last_elm` are never read
+        let data = vec![0, u64::MAX];
+        let mut b = BufferedVecReader::new(&data, 4);
+        assert_eq!(b.read(64), Some(0));
+        assert_eq!(b.read(5), None);
+        assert_eq!(b.read(4), Some(0b1111));
+        assert_eq!(b.read(1), None);
+        assert_eq!(b.position(), 68);
+
+        let data = vec![];
+        assert_eq!(BufferedVecReader::new(&data, 0).read(1), None);
+    }
+
     #[test]
     fn read_first_word() {
         let data = vec![0b1101000001000000000000000000010000000000000000000000000000000001];
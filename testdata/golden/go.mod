@@ -0,0 +1,5 @@
+module gibbon/testdata/golden
+
+go 1.21
+
+require github.com/prometheus/prometheus v0.47.2
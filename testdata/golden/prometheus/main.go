@@ -0,0 +1,60 @@
+// Prints the Prometheus XOR chunk of the samples in `src/prometheus_chunk.rs`,
+// as the `GOLDEN` constant of its tests.
+//
+//	cd testdata/golden
+//	go get github.com/prometheus/prometheus@v0.47.2
+//	go run ./prometheus
+//
+// v0.47.2 is the Go module version of Prometheus 2.47.2.
+package main
+
+import (
+	"fmt"
+	"strings"
+
+	"github.com/prometheus/prometheus/tsdb/chunkenc"
+)
+
+const t = 1600000000000
+
+func main() {
+	samples := []struct {
+		t int64
+		v float64
+	}{
+		{t, 0.5},
+		{t + 15000, 0.5},
+		{t + 30000, 0.75},
+		{t + 45001, 12.0},
+		{t + 60000, 12.0},
+		{t + 3660000, -3.25},
+		{t + 3675000, 1e-9},
+		{t + 3698192, 1e-9},
+		{t + 3779728, 7.0},
+	}
+
+	c := chunkenc.NewXORChunk()
+	app, err := c.Appender()
+	if err != nil {
+		panic(err)
+	}
+	for _, sample := range samples {
+		app.Append(sample.t, sample.v)
+	}
+
+	printGolden(c.Bytes())
+}
+
+func printGolden(bytes []byte) {
+	fmt.Printf("    const GOLDEN: [u8; %d] = [\n", len(bytes))
+	for len(bytes) > 0 {
+		n := min(16, len(bytes))
+		line := make([]string, n)
+		for i, b := range bytes[:n] {
+			line[i] = fmt.Sprintf("0x%02x,", b)
+		}
+		fmt.Printf("        %s\n", strings.Join(line, " "))
+		bytes = bytes[n:]
+	}
+	fmt.Println("    ];")
+}
@@ -0,0 +1,65 @@
+// Prints go-tsz's encoding of the samples in `src/tsz.rs`, as the `GOLDEN`
+// constant of its tests.
+//
+//	cd testdata/golden
+//	go get github.com/dgryski/go-tsz@4815cfd89fc090a7bef4a8fc0cb0f5695a23ceaa
+//	go run ./tsz
+//
+// That is the go-tsz the README links to, with 14-bit first deltas as
+// described in the module docs of `src/tsz.rs`.
+package main
+
+import (
+	"fmt"
+	"strings"
+
+	tsz "github.com/dgryski/go-tsz"
+)
+
+const t0 = 1500000000
+
+func main() {
+	samples := []struct {
+		t uint32
+		v float64
+	}{
+		{t0 + 60, 100.0},
+		{t0 + 120, 100.0},
+		{t0 + 181, 101.5},
+		{t0 + 240, 99.25},
+		{t0 + 300, -0.0},
+		{t0 + 360, 1e300},
+		{t0 + 600, 1e300},
+		{t0 + 601, 2.0},
+		{t0 + 5000, 2.5},
+	}
+	// Go has no negative zero constant
+	samples[4].v = negativeZero()
+
+	s := tsz.New(t0)
+	for _, sample := range samples {
+		s.Push(sample.t, sample.v)
+	}
+	s.Finish()
+
+	printGolden(s.Bytes())
+}
+
+func negativeZero() float64 {
+	zero := 0.0
+	return -zero
+}
+
+func printGolden(bytes []byte) {
+	fmt.Printf("    const GOLDEN: [u8; %d] = [\n", len(bytes))
+	for len(bytes) > 0 {
+		n := min(16, len(bytes))
+		line := make([]string, n)
+		for i, b := range bytes[:n] {
+			line[i] = fmt.Sprintf("0x%02x,", b)
+		}
+		fmt.Printf("        %s\n", strings.Join(line, " "))
+		bytes = bytes[n:]
+	}
+	fmt.Println("    ];")
+}